@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{NamespaceResolver, ResolveResult};
+use quick_xml::Reader;
+use xml_tree::canonical::{escape_attribute, escape_text};
+use xml_tree::CanonicalOptions;
+
+use crate::error::ReadError;
+use crate::quick_reader::{normalize_attribute_value, resolve_reference, AttributeType};
+
+/// Canonical XML straight from quick_xml events, without building a
+/// `FlatTree` first.
+///
+/// Gives the same bytes as `canonicalize_with(&read(..)?, &tree.document(), options)`
+/// for a whole document read with default `ReadOptions`, in one pass and
+/// keeping only the open elements and their namespace scopes around.
+pub struct CanonicalWriter<W: Write> {
+  out: W,
+  options: CanonicalOptions,
+  /// Elements waiting for their end tag.
+  open: Vec<Open>,
+  resolver: NamespaceResolver,
+  /// Whether the document element has been written, see `write_canonical`.
+  after_root: bool,
+}
+
+/// An open element, with the namespaces in scope at it and the ones the
+/// output has declared by then.
+struct Open {
+  qname: String,
+  scope: BTreeMap<String, String>,
+  rendered: BTreeMap<String, String>,
+}
+
+impl<W: Write> CanonicalWriter<W> {
+  /// A writer for canonical XML without comments.
+  pub fn new(out: W) -> Self {
+    Self::with_options(out, CanonicalOptions::default())
+  }
+
+  pub fn with_options(out: W, options: CanonicalOptions) -> Self {
+    Self { out, options, open: Vec::new(), resolver: NamespaceResolver::default(), after_root: false }
+  }
+
+  /// Feed every event of `reader` through `write_event` up to the end of the
+  /// input. Errors carry the byte position like `quick_reader::read`.
+  pub fn write_reader<R: BufRead>(&mut self, mut reader: Reader<R>, buf: &mut Vec<u8>) -> Result<(), ReadError> {
+    loop {
+      buf.clear();
+      let event = reader.read_event_into(buf).map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+      self.write_event(&event).map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+      if let Event::Eof = event {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Write the canonical form of one event. End tags are taken from the
+  /// matching start tag, checking that they match is up to the reader.
+  /// `Event::Eof` writes the `trailing_newline` if there is one.
+  pub fn write_event(&mut self, event: &Event) -> Result<(), quick_xml::Error> {
+    match event {
+      Event::Start(e) => self.start(e)?,
+      Event::Empty(e) => {
+        self.start(e)?;
+        self.end()?;
+      }
+      Event::End(_) => self.end()?,
+      Event::Eof if self.options.trailing_newline => self.out.write_all(b"\n")?,
+      // Only whitespace can sit outside the document element.
+      Event::Text(e) if !self.open.is_empty() => escape_text(&mut self.out, &e.decode()?)?,
+      Event::CData(e) if !self.open.is_empty() => escape_text(&mut self.out, &e.decode()?)?,
+      Event::GeneralRef(e) if !self.open.is_empty() => escape_text(&mut self.out, &resolve_reference(e)?)?,
+      Event::Comment(e) if self.options.with_comments => {
+        let text = e.decode()?;
+        self.separated(|w| write!(w, "<!--{text}-->"))?;
+      }
+      Event::PI(e) => {
+        let decoder = e.attributes().decoder();
+        let target = decoder.decode(e.target())?;
+        let content = decoder.decode(e.content())?;
+        let data = content.trim_start();
+        self.separated(|w| match data {
+          "" => write!(w, "<?{target}?>"),
+          data => write!(w, "<?{target} {data}?>"),
+        })?;
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+
+  /// The output written so far. Anything still open is left unclosed.
+  pub fn into_inner(self) -> W {
+    self.out
+  }
+
+  fn start(&mut self, e: &BytesStart) -> Result<(), quick_xml::Error> {
+    self.resolver.push(e)?;
+
+    let empty = BTreeMap::new();
+    let (mut scope, rendered) = match self.open.last() {
+      Some(parent) => (parent.scope.clone(), &parent.rendered),
+      None => (BTreeMap::new(), &empty),
+    };
+
+    // (uri, local name, qualified name, value) of the attributes that are
+    // not declarations. Like in the reader the last of a repeated attribute
+    // or declaration counts.
+    let decoder = e.decoder();
+    let mut attributes: Vec<(String, String, String, String)> = Vec::new();
+    for attr in e.attributes().with_checks(false).flatten() {
+      let key = decoder.decode(attr.key.as_ref())?;
+      let key = &*key;
+      let value = normalize_attribute_value(&decoder.decode(&attr.value)?, AttributeType::Cdata, None)?;
+
+      if key == "xmlns" {
+        scope.insert(String::new(), value);
+      } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+        scope.insert(prefix.to_string(), value);
+      } else if let Some(repeated) = attributes.iter_mut().find(|(_, _, qname, _)| qname == key) {
+        repeated.3 = value;
+      } else {
+        let (resolved, local) = self.resolver.resolve_attribute(attr.key);
+        let uri = match resolved {
+          ResolveResult::Bound(ns) => decoder.decode(ns.into_inner())?.into_owned(),
+          _ => String::new(),
+        };
+        attributes.push((uri, decoder.decode(local.as_ref())?.into_owned(), key.to_string(), value));
+      }
+    }
+    attributes.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    let qname = decoder.decode(e.name().as_ref())?.into_owned();
+    // Exclusive C14N only declares what the element and its attributes use,
+    // see `xml_tree::CanonicalOptions::exclusive`.
+    let visible: BTreeMap<String, String> = if self.options.exclusive {
+      let element = qname.split_once(':').map_or("", |(prefix, _)| prefix);
+      let used = attributes.iter().filter_map(|(_, _, key, _)| key.split_once(':').map(|(prefix, _)| prefix));
+      let listed = self.options.inclusive_prefixes.iter().map(|prefix| &**prefix);
+      [element]
+        .into_iter()
+        .chain(used)
+        .chain(listed)
+        .filter_map(|prefix| scope.get_key_value(prefix))
+        .map(|(prefix, uri)| (prefix.clone(), uri.clone()))
+        .collect()
+    } else {
+      scope.clone()
+    };
+
+    write!(self.out, "<{qname}")?;
+    for (prefix, uri) in &visible {
+      if rendered.get(prefix).map_or("", String::as_str) == uri {
+        continue;
+      }
+      if prefix.is_empty() {
+        write!(self.out, " xmlns=\"")?;
+      } else {
+        write!(self.out, " xmlns:{prefix}=\"")?;
+      }
+      escape_attribute(&mut self.out, uri)?;
+      self.out.write_all(b"\"")?;
+    }
+    for (_, _, key, value) in &attributes {
+      write!(self.out, " {key}=\"")?;
+      escape_attribute(&mut self.out, value)?;
+      self.out.write_all(b"\"")?;
+    }
+    self.out.write_all(b">")?;
+
+    let rendered = rendered.clone().into_iter().chain(visible).collect();
+    self.after_root |= self.open.is_empty();
+    self.open.push(Open { qname, scope, rendered });
+    Ok(())
+  }
+
+  fn end(&mut self) -> Result<(), quick_xml::Error> {
+    if let Some(open) = self.open.pop() {
+      self.resolver.pop();
+      write!(self.out, "</{}>", open.qname)?;
+    }
+    Ok(())
+  }
+
+  /// Write a comment or PI, with the line feed that separates it from the
+  /// document element when it is outside of it.
+  fn separated(&mut self, write: impl FnOnce(&mut W) -> std::io::Result<()>) -> Result<(), quick_xml::Error> {
+    let outside = self.open.is_empty();
+    if outside && self.after_root {
+      self.out.write_all(b"\n")?;
+    }
+    write(&mut self.out)?;
+    if outside && !self.after_root {
+      self.out.write_all(b"\n")?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::quick_reader::read;
+
+  fn streamed(xml: &str, options: CanonicalOptions) -> String {
+    let mut writer = CanonicalWriter::with_options(Vec::new(), options);
+    writer.write_reader(Reader::from_str(xml), &mut Vec::new()).unwrap();
+    String::from_utf8(writer.into_inner()).unwrap()
+  }
+
+  #[test]
+  fn matches_tree_canonicalization() {
+    let documents = [
+      "<root/>",
+      r#"<?xml version="1.0"?><!--head--><?pi  data ?><doc b="2" a="1">x &amp; &lt;y&gt;&#xD;<e/></doc><!--tail--><?end?>"#,
+      r#"<doc xmlns="urn:d" xmlns:a="urn:a"><a:e a:z="1" b="2" xml:lang="en"/><f xmlns=""><g xmlns="urn:d"/></f></doc>"#,
+      r#"<a xmlns:p="urn:p"><p:b xmlns:p="urn:q" p:x="1"><c xmlns:p="urn:q"/></p:b></a>"#,
+      "<a v=\"tab\there&#9;\nnew\">\n  text   &#x20AC; é\n</a>",
+      r#"<e xmlns:a="u1" xmlns:a="u2" a:x="1" a:x="2"><a:f/></e>"#,
+      r#"<a xmlns="urn:a" xmlns:u="urn:u"><b xmlns=""><u:c u:x="1"/><d/></b></a>"#,
+    ];
+
+    for xml in documents {
+      let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+      for (with_comments, trailing_newline, exclusive) in [(false, false, false), (true, false, false), (false, true, false), (false, false, true)] {
+        let options = CanonicalOptions { with_comments, trailing_newline, exclusive, ..CanonicalOptions::default() };
+        let expected = xml_tree::canonicalize_with(&tree, &tree.document(), &options);
+        assert_eq!(streamed(xml, options), expected, "{xml}");
+      }
+    }
+
+    // Repeats count once, the last one like in the reader.
+    let xml = r#"<e xmlns:a="u1" a:x="1" xmlns:a="u2" a:x="2" y="1" y="2"/>"#;
+    assert_eq!(streamed(xml, CanonicalOptions::default()), r#"<e xmlns:a="u2" y="2" a:x="2"></e>"#);
+  }
+
+  #[test]
+  fn exclusive_declarations() {
+    let xml = r#"<a xmlns="urn:a" xmlns:u="urn:u" xmlns:v="urn:v"><b xmlns=""><u:c/><d/></b><e/></a>"#;
+    let options = CanonicalOptions { exclusive: true, ..CanonicalOptions::default() };
+    assert_eq!(streamed(xml, options), r#"<a xmlns="urn:a"><b xmlns=""><u:c xmlns:u="urn:u"></u:c><d></d></b><e></e></a>"#);
+
+    let options = CanonicalOptions { exclusive: true, inclusive_prefixes: vec!["v".into()], ..CanonicalOptions::default() };
+    assert_eq!(
+      streamed(xml, options),
+      r#"<a xmlns="urn:a" xmlns:v="urn:v"><b xmlns=""><u:c xmlns:u="urn:u"></u:c><d></d></b><e></e></a>"#
+    );
+  }
+
+  #[test]
+  fn cdata_and_errors() {
+    assert_eq!(streamed("<a><![CDATA[<x> & y]]></a>", CanonicalOptions::default()), "<a>&lt;x&gt; &amp; y</a>");
+
+    for xml in ["<a>&bogus;</a>", r#"<a v="&bogus;"/>"#] {
+      let mut writer = CanonicalWriter::new(Vec::new());
+      let error = writer.write_reader(Reader::from_str(xml), &mut Vec::new()).unwrap_err();
+      assert!(matches!(error.kind, crate::ReadErrorKind::Xml(quick_xml::Error::Escape(_))), "{xml}");
+    }
+  }
+}
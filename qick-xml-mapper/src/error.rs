@@ -0,0 +1,102 @@
+use std::fmt;
+
+/// Error returned by `quick_reader::read`.
+///
+/// `position` is the byte offset into the input where the reader was
+/// when the error happened.
+#[derive(Debug)]
+pub struct ReadError {
+  pub position: u64,
+  pub kind: ReadErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ReadErrorKind {
+  /// quick_xml failed to parse or decode the input.
+  Xml(quick_xml::Error),
+  /// A declaration needed a new namespace id but the registry was full,
+  /// either at `ReadOptions::max_namespaces` or out of `u16` ids.
+  NamespaceRegistryFull { prefix: Box<str> },
+  /// A tag repeats an attribute, only reported with `ReadOptions::strict`.
+  DuplicateAttribute { name: Box<str> },
+  /// A tag has more attributes than `ReadOptions::max_attributes_per_element`.
+  TooManyAttributes { limit: usize },
+  /// The input ended without an element, only reported with
+  /// `ReadOptions::strict`.
+  NoRootElement,
+}
+
+/// Something malformed the reader worked around instead of failing, see
+/// `ReadOptions::on_recover`. `position` is a byte offset like in `ReadError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryEvent {
+  pub position: u64,
+  pub kind: RecoveryKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryKind {
+  /// An end tag that does not close the open element was ignored. quick_xml
+  /// only lets these through with `check_end_names` off, and at the top
+  /// level with `allow_unmatched_ends` on.
+  StrayEndTag { name: Box<str> },
+  /// An attribute quick_xml could not parse was skipped.
+  MalformedAttribute,
+  /// An attribute or declaration was repeated, the last one was kept.
+  DuplicateAttribute { name: Box<str> },
+}
+
+impl ReadError {
+  pub fn new(position: u64, kind: ReadErrorKind) -> Self {
+    Self { position, kind }
+  }
+
+  pub(crate) fn xml(position: u64, error: impl Into<quick_xml::Error>) -> Self {
+    Self::new(position, ReadErrorKind::Xml(error.into()))
+  }
+
+  /// 1-based line and column of `position` in `source`.
+  ///
+  /// The reader only knows byte offsets, so the caller has to hand back
+  /// the input it parsed. Columns are counted in bytes.
+  pub fn line_column(&self, source: &[u8]) -> (usize, usize) {
+    let end = (self.position as usize).min(source.len());
+    let before = &source[..end];
+
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    (line, end - line_start + 1)
+  }
+}
+
+impl fmt::Display for ReadError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.kind {
+      ReadErrorKind::Xml(e) => write!(f, "{e} (at byte {})", self.position),
+      ReadErrorKind::NamespaceRegistryFull { prefix } => {
+        write!(f, "namespace registry is full, can not register prefix `{prefix}` (at byte {})", self.position)
+      }
+      ReadErrorKind::DuplicateAttribute { name } => {
+        write!(f, "duplicate attribute `{name}` (at byte {})", self.position)
+      }
+      ReadErrorKind::TooManyAttributes { limit } => {
+        write!(f, "element has more than {limit} attributes (at byte {})", self.position)
+      }
+      ReadErrorKind::NoRootElement => {
+        write!(f, "no root element before the end of input (at byte {})", self.position)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ReadError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match &self.kind {
+      ReadErrorKind::Xml(e) => Some(e),
+      ReadErrorKind::NamespaceRegistryFull { .. }
+      | ReadErrorKind::DuplicateAttribute { .. }
+      | ReadErrorKind::TooManyAttributes { .. }
+      | ReadErrorKind::NoRootElement => None,
+    }
+  }
+}
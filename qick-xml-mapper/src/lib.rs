@@ -1,2 +1,6 @@
+pub mod canonical_writer;
+pub mod error;
 pub mod quick_reader;
-pub mod quick_writer;
\ No newline at end of file
+pub mod quick_writer;
+
+pub use error::{ReadError, ReadErrorKind, RecoveryEvent, RecoveryKind};
\ No newline at end of file
@@ -1,150 +1,810 @@
-use xml_tree::{FlatTree, Node, XAttribute, XNode};
-use quick_xml::events::{BytesStart, Event};
-use std::collections::BTreeMap;
+use crate::error::{ReadError, ReadErrorKind, RecoveryEvent, RecoveryKind};
+use xml_tree::{split_qname, FlatTree, Node, XAttribute, XNode, XmlDecl};
+use quick_xml::escape::{resolve_predefined_entity, unescape_with, EscapeError};
+use quick_xml::events::{BytesRef, BytesStart, Event};
+use quick_xml::name::{NamespaceResolver, QName, ResolveResult};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use quick_xml::{Reader};
 use std::io::BufRead;
 
+/// Options for `read_with`.
+///
+/// The quick_xml flags here are written into the reader's config before
+/// parsing, so they win over however the reader was set up.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+  /// Trim whitespace around text. Whitespace-only text between tags is
+  /// dropped entirely instead of becoming text nodes.
+  pub trim_text: bool,
+  /// Have quick_xml report `<a/>` as a start and an end event. The node count
+  /// is the same either way, an empty element is one tag node, but the tag
+  /// is no longer marked `self_closing`.
+  pub expand_empty_elements: bool,
+  /// Drop whitespace-only text nodes, except under `xml:space="preserve"`.
+  /// Unlike `trim_text` this knows about `xml:space` scoping.
+  pub trim_whitespace: bool,
+  /// Store whitespace-only text as `XNode::Whitespace`, the indentation
+  /// between elements for example, so later passes can tell it apart from
+  /// content without scanning it again. What `trim_whitespace` drops is gone
+  /// either way.
+  pub whitespace_nodes: bool,
+  /// Store comments as `XNode::Comment`. Turned off they are skipped at
+  /// parse time, for documents that are only ever canonicalized without
+  /// comments. Text on either side of a skipped comment ends up as one node.
+  pub keep_comments: bool,
+  /// Fail with `ReadErrorKind::NamespaceRegistryFull` once this many
+  /// namespaces are registered. The registry can never hold more than its
+  /// `u16` ids allow, that limit is always enforced.
+  pub max_namespaces: Option<usize>,
+  /// Fail with `ReadErrorKind::TooManyAttributes` on a tag with more than
+  /// this many attributes, namespace declarations and repeats included.
+  pub max_attributes_per_element: Option<usize>,
+  /// Schema type of an attribute, given the element and attribute qualified
+  /// names. Without it every attribute is CDATA, which is all a parser can
+  /// know without a DTD.
+  pub attribute_type: Option<fn(&str, &str) -> AttributeType>,
+  /// Replacement text for an entity reference in text or an attribute value,
+  /// given the entity name, for entities a DTD would declare. The predefined
+  /// entities and character references never get here. Without it, or when
+  /// it returns `None`, an unknown entity is an error.
+  pub resolve_entity: Option<fn(&str) -> Option<&'static str>>,
+  /// Rewrite element and attribute local names as they are read, to case
+  /// fold names from sources with inconsistent casing for example. Prefixes
+  /// and namespace declarations are left alone.
+  pub name_transform: Option<fn(&str) -> String>,
+  /// Record the byte range each node was read from, see `Node::source_span`.
+  /// Elements span from their start tag to the end of their end tag.
+  pub record_spans: bool,
+  /// Fail with `ReadErrorKind::DuplicateAttribute` when a tag repeats an
+  /// attribute, by qualified name or by namespace uri and local name.
+  /// Otherwise the last one wins, for attributes and namespace declarations
+  /// alike.
+  ///
+  /// Also fail with `ReadErrorKind::NoRootElement` when the input ends
+  /// without any element, where otherwise the tree just comes back empty.
+  pub strict: bool,
+  /// Keep entity and character references in text as written, so the
+  /// round-trip writer reproduces `&#38;` and `&amp;` exactly. The tree is
+  /// marked with `FlatTree::raw_text`, canonical output is not affected.
+  pub raw_text: bool,
+  /// Called for everything malformed the reader works around rather than
+  /// failing on, to log or count what got fixed. Strict mode turns some of
+  /// these into errors instead.
+  pub on_recover: Option<fn(RecoveryEvent)>,
+  /// Keep PI data exactly as written, with the whitespace that separates it
+  /// from the target, so the round-trip writer reproduces `<?pi   x ?>`.
+  /// Canonical output drops that whitespace either way.
+  pub keep_pi_whitespace: bool,
+}
+
+/// How an attribute value is normalized, XML 1.0 section 3.3.3.
+///
+/// Both replace literal tab, newline and carriage return with a space,
+/// character references like `&#xA;` are kept as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeType {
+  /// Whitespace inside the value is kept as is.
+  Cdata,
+  /// NMTOKENS, IDREFS and the other tokenized types: spaces are trimmed
+  /// and runs of them collapse into one.
+  Tokenized,
+}
+
+impl ReadOptions {
+  /// The options matching how `reader` is already configured.
+  fn from_config<R>(reader: &Reader<R>) -> Self {
+    Self {
+      trim_text: reader.config().trim_text_start && reader.config().trim_text_end,
+      expand_empty_elements: reader.config().expand_empty_elements,
+      ..Self::default()
+    }
+  }
+}
+
+impl Default for ReadOptions {
+  fn default() -> Self {
+    Self {
+      trim_text: false,
+      expand_empty_elements: false,
+      trim_whitespace: false,
+      whitespace_nodes: false,
+      keep_comments: true,
+      max_namespaces: None,
+      max_attributes_per_element: None,
+      attribute_type: None,
+      resolve_entity: None,
+      name_transform: None,
+      record_spans: false,
+      strict: false,
+      raw_text: false,
+      on_recover: None,
+      keep_pi_whitespace: false,
+    }
+  }
+}
+
 /// Parse XML from a `quick_xml::Reader` into a `FlatTree`.
 ///
 /// The caller provides the reader (configured however they want) and
-/// a reusable event buffer.
-pub fn read<R: BufRead>(mut reader: Reader<R>, buf: &mut Vec<u8>) -> Result<FlatTree, quick_xml::Error> {
-  let mut tree = FlatTree::new();
-  let mut node_stack: Vec<Node> = Vec::new();
-  let mut current_node = tree.as_node();
+/// a reusable event buffer. Errors carry the byte position they happened at.
+pub fn read<R: BufRead>(reader: Reader<R>, buf: &mut Vec<u8>) -> Result<FlatTree, ReadError> {
+  let options = ReadOptions::from_config(&reader);
+  parse(TreeState::new(FlatTree::new()), reader, buf, &options)
+}
+
+/// Parse a fragment, any number of top level nodes instead of a single
+/// document element.
+///
+/// `read` already accepts several roots, the difference is that text
+/// between them is content here and kept as is, where `read` drops the
+/// whitespace around the document element. The result is a forest, see
+/// `FlatTree::top_level_nodes`. Canonicalizing all of it as a document drops
+/// that text, see `xml_tree::canonicalize`.
+pub fn read_fragment<R: BufRead>(reader: Reader<R>, buf: &mut Vec<u8>) -> Result<FlatTree, ReadError> {
+  let options = ReadOptions::from_config(&reader);
+  let mut state = TreeState::new(FlatTree::new());
+  state.fragment = true;
+  parse(state, reader, buf, &options)
+}
+
+/// Parse a string with a default configured reader.
+///
+/// This is the `str::parse` of the crate, `FromStr` itself can not be
+/// implemented for `FlatTree` from here since neither type nor trait is ours.
+pub fn read_str(xml: &str) -> Result<FlatTree, ReadError> {
+  read(Reader::from_str(xml), &mut Vec::new())
+}
+
+/// Parse independent documents across the rayon thread pool, results in
+/// input order. Each document gets its own default configured reader.
+#[cfg(feature = "rayon")]
+pub fn read_many<'a, I>(inputs: I) -> Vec<Result<FlatTree, ReadError>>
+where
+  I: rayon::iter::IntoParallelIterator<Item = &'a [u8]>,
+{
+  use rayon::iter::ParallelIterator;
+
+  inputs
+    .into_par_iter()
+    .map(|input| read(Reader::from_reader(input), &mut Vec::new()))
+    .collect()
+}
+
+/// Parse a gzip compressed document with a default configured reader.
+///
+/// The decoder is buffered here, so `input` can be a plain `File`. Errors
+/// from decompression come back as `ReadErrorKind::Xml` I/O errors.
+#[cfg(feature = "flate2")]
+pub fn read_gzip<R: std::io::Read>(input: R) -> Result<FlatTree, ReadError> {
+  let decoder = std::io::BufReader::new(flate2::read::GzDecoder::new(input));
+  read(Reader::from_reader(decoder), &mut Vec::new())
+}
+
+/// Like `read`, but the reader is configured from `options` first.
+pub fn read_with<R: BufRead>(mut reader: Reader<R>, buf: &mut Vec<u8>, options: &ReadOptions) -> Result<FlatTree, ReadError> {
+  let config = reader.config_mut();
+  config.trim_text(options.trim_text);
+  config.expand_empty_elements = options.expand_empty_elements;
+
+  parse(TreeState::new(FlatTree::new()), reader, buf, options)
+}
+
+fn parse<R: BufRead>(mut state: TreeState, mut reader: Reader<R>, buf: &mut Vec<u8>, options: &ReadOptions) -> Result<FlatTree, ReadError> {
+  if options.record_spans {
+    state.spans = Some(Vec::new());
+  }
+  state.tree.set_raw_text(options.raw_text);
 
   loop {
     buf.clear();
-    match reader.read_event_into(buf)? {
-        Event::Start(ref e) => {
-          let xnode = build_tag(&mut tree, e, &reader);
-          node_stack.push(current_node.clone());
-          current_node = current_node.push(&mut tree, xnode);
-        }
-        Event::End(ref e) => {
-          let (local_name, prefix) = e.name().decompose();
-          let local = std::str::from_utf8(local_name.as_ref()).unwrap_or("");
-          let prefix_owned = prefix.map(|p| std::str::from_utf8(p.as_ref()).unwrap_or("").to_string());
-          let ns_id = tree.find_namespace(prefix_owned.as_deref());
+    state.event_start = reader.buffer_position();
+    let event = reader
+      .read_event_into(buf)
+      .map_err(|e| ReadError::xml(reader.error_position(), e))?;
 
-          if current_node.compare_name(&tree, ns_id, local){
-            let node = node_stack.pop();
+    if let Event::Eof = event {
+      state.finish_text(options);
+      break;
+    }
+    state.handle(&event, &reader, options)?;
+  }
 
-            if node.is_none(){
-              continue;
-            }
+  if options.strict && state.tree.node_counts().tags == 0 {
+    return Err(ReadError::new(reader.buffer_position(), ReadErrorKind::NoRootElement));
+  }
 
-            current_node = node.unwrap();  
-          } /*else { // Handling broken xml, like <root><e1></root>... quick_xml returns an error when this happens... Sadness.
-              for (i, node) in node_stack.iter().enumerate().rev()  {
-                if node.compare_name(&tree, ns_id, local){
+  if let Some(spans) = state.spans.take() {
+    state.tree.set_source_spans(spans);
+  }
+  Ok(state.tree)
+}
 
-                  current_node = node.clone();
-                  node_stack.truncate(i);
-                  break;
-                }
-              }
-          }*/
+/// Parse back-to-back documents from one stream, one `FlatTree` per
+/// document element.
+///
+/// A document ends with the end tag of its element. Comments, PIs and a
+/// declaration after it belong to the next document, and whitespace between
+/// documents is skipped. The iterator ends at EOF or after the first error.
+pub fn read_stream<R: BufRead>(reader: Reader<R>, buf: &mut Vec<u8>) -> ReadStream<'_, R> {
+  let options = ReadOptions::from_config(&reader);
+  ReadStream { reader, buf, options, done: false }
+}
+
+/// Iterator returned by `read_stream`.
+pub struct ReadStream<'b, R> {
+  reader: Reader<R>,
+  buf: &'b mut Vec<u8>,
+  options: ReadOptions,
+  done: bool,
+}
+
+impl<R: BufRead> Iterator for ReadStream<'_, R> {
+  type Item = Result<FlatTree, ReadError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    let mut state = TreeState::new(FlatTree::new());
+
+    loop {
+      self.buf.clear();
+      let event = match self.reader.read_event_into(self.buf) {
+        Ok(event) => event,
+        Err(e) => {
+          self.done = true;
+          return Some(Err(ReadError::xml(self.reader.error_position(), e)));
         }
-        Event::Empty(ref e) => {
-          let node = build_tag(&mut tree, e, &reader);
-          _ = current_node.push(&mut tree, node);
+      };
+
+      if let Event::Eof = event {
+        self.done = true;
+        // Trailing comments or PIs with no element after them.
+        return (!state.tree.is_empty()).then_some(Ok(state.tree));
+      }
+      if let Err(e) = state.handle(&event, &self.reader, &self.options) {
+        self.done = true;
+        return Some(Err(e));
+      }
+      if matches!(event, Event::End(_) | Event::Empty(_)) && state.node_stack.is_empty() {
+        return Some(Ok(state.tree));
+      }
+    }
+  }
+}
+
+/// Parse a document one record at a time.
+///
+/// Every `record_name` element directly under the root is built into its own
+/// `FlatTree` and handed to `f`, then dropped, so memory stays bounded by the
+/// largest record rather than the document. Anything between records is
+/// skipped. Record trees start out with the namespaces declared on the root
+/// so prefixed names still resolve, and the record element declares them
+/// like `Node::extract_subtree` does, unless it redeclares them itself.
+pub fn read_records<R: BufRead, F: FnMut(FlatTree)>(mut reader: Reader<R>, buf: &mut Vec<u8>, record_name: &str, mut f: F) -> Result<(), ReadError> {
+  let options = ReadOptions::default();
+  // Only the root tag is kept around, for the namespaces it declares.
+  let mut root = TreeState::new(FlatTree::new());
+  let mut record: Option<TreeState> = None;
+  let mut depth = 0usize;
+
+  loop {
+    buf.clear();
+    let event = reader
+      .read_event_into(buf)
+      .map_err(|e| ReadError::xml(reader.error_position(), e))?;
+
+    if let Event::Eof = event {
+      break;
+    }
+
+    if let Some(state) = &mut record {
+      state.handle(&event, &reader, &options)?;
+      if state.node_stack.is_empty() {
+        f(record.take().unwrap().tree);
+        depth -= 1;
+      }
+      continue;
+    }
+
+    match &event {
+      Event::Start(e) | Event::Empty(e) if depth == 1 && e.local_name().as_ref() == record_name.as_bytes() => {
+        let mut tree = FlatTree::new();
+        for (_, prefix, uri) in root.tree.namespaces() {
+          tree.add_namespace(prefix.into(), uri.into());
         }
-        Event::Text(ref e) => {
-          let text = e.decode()?.into_owned().into_boxed_str();
-          _ = current_node.push(&mut tree, XNode::Text(text));
+
+        let mut state = TreeState::new(tree);
+        state.resolver = root.resolver.clone();
+        state.handle(&event, &reader, &options)?;
+        inherit_declarations(&root.tree, &mut state.tree);
+        if state.node_stack.is_empty() {
+          f(state.tree);
+        } else {
+          depth += 1;
+          record = Some(state);
         }
-        Event::Comment(ref e) => {
-          let text = e.decode()?.into_owned().into_boxed_str();
-          _ = current_node.push(&mut tree, XNode::Comment(text));
+      }
+      Event::Start(_) => {
+        if depth == 0 {
+          root.handle(&event, &reader, &options)?;
         }
-        Event::PI(ref e) => {
-          let target = std::str::from_utf8(e.target())
-            .unwrap_or("")
-            .to_string()
-            .into_boxed_str();
-          let raw_content = e.content();
-          let data = if raw_content.is_empty() {
-            None
-          } else {
-            Some(
-              std::str::from_utf8(raw_content)
-                .unwrap_or("")
-                .trim()
-                .to_string()
-                .into_boxed_str(),
-            )
-          };
-          _ = current_node.push(&mut tree, XNode::ProcessingInstruction { target, data });
+        depth += 1;
+      }
+      Event::End(_) => depth = depth.saturating_sub(1),
+      _ => {}
+    }
+  }
+
+  Ok(())
+}
+
+/// Turns reader events into nodes, tracking the open elements.
+struct TreeState {
+  tree: FlatTree,
+  node_stack: Vec<Node>,
+  current_node: Node,
+  /// `xml:space="preserve"` in effect, parallel to `node_stack`.
+  preserve_stack: Vec<bool>,
+  preserve: bool,
+  /// Parsing a fragment, top level text is kept.
+  fragment: bool,
+  /// Source spans when recording them, parallel to the tree's nodes.
+  spans: Option<Vec<(usize, usize)>>,
+  /// Reader position before the event being handled.
+  event_start: u64,
+  /// The namespace bindings in scope, the same resolver `NsReader` uses.
+  resolver: NamespaceResolver,
+}
+
+impl TreeState {
+  fn new(tree: FlatTree) -> Self {
+    let current_node = tree.document();
+    Self { tree, node_stack: Vec::new(), current_node, preserve_stack: Vec::new(), preserve: false, fragment: false, spans: None, event_start: 0, resolver: NamespaceResolver::default() }
+  }
+
+  /// A run of text is only complete once something else shows up, that is
+  /// when a whitespace-only run can be dropped or marked as whitespace.
+  fn finish_text(&mut self, options: &ReadOptions) {
+    let trim = options.trim_whitespace && !self.preserve;
+    if !trim && !options.whitespace_nodes {
+      return;
+    }
+
+    let last = self.tree.len().wrapping_sub(1);
+    let depth = self.current_node.depth(&self.tree) + 1;
+    if self.tree.node(last).is_some_and(|n| n.depth(&self.tree) == depth)
+      && let Some(xnode) = self.tree.value_mut(last)
+      && let XNode::Text(text) = xnode
+      && is_whitespace(text)
+    {
+      if trim {
+        self.tree.pop();
+        if let Some(spans) = &mut self.spans {
+          spans.truncate(self.tree.len());
         }
-        Event::Eof => break,
-        _ => {} // I need to think about how i want to support some of the other nodes i have neglected here. 
+      } else {
+        *xnode = XNode::Whitespace(std::mem::take(text));
+      }
+    }
+  }
+
+  /// Give a node pushed by the current event its span, or stretch the last
+  /// span when text was merged into the previous node.
+  fn record_span<R: BufRead>(&mut self, event: &Event, reader: &Reader<R>) {
+    let Some(spans) = &mut self.spans else { return };
+    let end = reader.buffer_position() as usize;
+
+    if self.tree.len() > spans.len() {
+      spans.push((self.event_start as usize, end));
+    } else if matches!(event, Event::Text(_) | Event::CData(_) | Event::GeneralRef(_))
+      && let Some(last) = spans.last_mut()
+    {
+      last.1 = end;
     }
   }
 
-  Ok(tree)
+  /// Open the namespace scope of a start tag.
+  fn push_scope<R: BufRead>(&mut self, e: &BytesStart, reader: &Reader<R>) -> Result<(), ReadError> {
+    self.resolver.push(e).map_err(|error| ReadError::xml(reader.buffer_position(), error))
+  }
+
+  fn handle<R: BufRead>(&mut self, event: &Event, reader: &Reader<R>, options: &ReadOptions) -> Result<(), ReadError> {
+    if let Event::Comment(_) = event && !options.keep_comments {
+      return Ok(());
+    }
+    if !matches!(event, Event::Text(_) | Event::CData(_) | Event::GeneralRef(_)) {
+      self.finish_text(options);
+    }
+
+    match event {
+      Event::Start(e) => {
+        self.push_scope(e, reader)?;
+        let xnode = build_tag(&mut self.tree, &self.resolver, e, reader, false, options)?;
+        self.preserve_stack.push(self.preserve);
+        self.preserve = xml_space(&xnode).unwrap_or(self.preserve);
+        self.node_stack.push(self.current_node.clone());
+        self.current_node = self.current_node.push(&mut self.tree, xnode);
+      }
+      Event::End(e) => {
+        let (resolved, local_name) = self.resolver.resolve_element(e.name());
+        let local = decode(reader, local_name.as_ref())?;
+        let local = transform_name(&local, options);
+        let uri = match resolved {
+          ResolveResult::Bound(ns) => Some(decode(reader, ns.as_ref())?.into_owned()),
+          _ => None,
+        };
+        let same_name = match self.current_node.value(&self.tree) {
+          Some(XNode::Tag { namespace, name, .. }) => **name == *local && self.tree.namespace_uri(*namespace) == uri.as_deref(),
+          _ => false,
+        };
+
+        if same_name {
+          self.resolver.pop();
+          let node = self.node_stack.pop();
+
+          if node.is_none(){
+            return Ok(());
+          }
+
+          self.preserve = self.preserve_stack.pop().unwrap_or(false);
+
+          if let Some(spans) = &mut self.spans
+            && let Some(span) = spans.get_mut(self.current_node.index())
+          {
+            span.1 = reader.buffer_position() as usize;
+          }
+          self.current_node = node.unwrap();
+        } else {
+          let name = decode(reader, e.name().as_ref())?.into();
+          recover(options, reader, RecoveryKind::StrayEndTag { name });
+        }
+        /*else { // Handling broken xml, like <root><e1></root>... quick_xml returns an error when this happens... Sadness.
+            for (i, node) in self.node_stack.iter().enumerate().rev()  {
+              if node.compare_name(&self.tree, ns_id, local){
+
+                self.current_node = node.clone();
+                self.node_stack.truncate(i);
+                break;
+              }
+            }
+        }*/
+      }
+      Event::Empty(e) => {
+        self.push_scope(e, reader)?;
+        let node = build_tag(&mut self.tree, &self.resolver, e, reader, true, options);
+        self.resolver.pop();
+        _ = self.current_node.push(&mut self.tree, node?);
+      }
+      Event::Text(e) => {
+        let text = e
+          .decode()
+          .map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+
+        // Outside of any element whitespace is not content. A reader handed
+        // over mid-stream may also still be sitting on a BOM.
+        if self.node_stack.is_empty() && !self.fragment && is_whitespace(text.trim_start_matches('\u{feff}')) {
+          return Ok(());
+        }
+        push_text(&mut self.tree, &self.current_node, &text);
+      }
+      // A CDATA section is just text, raw text has to escape it though.
+      Event::CData(e) => {
+        let text = e
+          .decode()
+          .map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+        let text = if options.raw_text { quick_xml::escape::escape(text) } else { text };
+        push_text(&mut self.tree, &self.current_node, &text);
+      }
+      Event::GeneralRef(e) if options.raw_text => {
+        let name = e.decode().map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+        push_text(&mut self.tree, &self.current_node, &format!("&{name};"));
+      }
+      Event::GeneralRef(e) => {
+        let text = match resolve_reference(e) {
+          Ok(text) => Cow::Owned(text),
+          Err(error) => {
+            let name = e.decode().map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+            match options.resolve_entity.and_then(|f| f(&name)) {
+              Some(text) => Cow::Borrowed(text),
+              None => return Err(ReadError::xml(reader.buffer_position(), error)),
+            }
+          }
+        };
+        push_text(&mut self.tree, &self.current_node, &text);
+      }
+      Event::Comment(e) => {
+        let text = e
+          .decode()
+          .map_err(|e| ReadError::xml(reader.buffer_position(), e))?
+          .into_owned()
+          .into_boxed_str();
+        _ = self.current_node.push(&mut self.tree, XNode::Comment(text));
+      }
+      Event::PI(e) => {
+        let target = decode(reader, e.target())?.into_owned().into_boxed_str();
+        // Whitespace after the target only separates it from the data, the
+        // data itself runs up to `?>` trailing whitespace and all.
+        let content = decode(reader, e.content())?;
+        let content = if options.keep_pi_whitespace { &*content } else { content.trim_start() };
+        let data = if content.is_empty() {
+          None
+        } else {
+          Some(content.to_string().into_boxed_str())
+        };
+        _ = self.current_node.push(&mut self.tree, XNode::ProcessingInstruction { target, data });
+      }
+      // Kept beside the nodes, the declaration is not a PI. Without a version
+      // or with a broken pseudo-attribute it is not a declaration at all.
+      Event::Decl(e) => {
+        let error = |e: quick_xml::Error| ReadError::xml(reader.buffer_position(), e);
+        let text = |value: &[u8]| decode(reader, value).map(Into::into);
+        let decl = XmlDecl {
+          version: text(&e.version().map_err(error)?)?,
+          encoding: e.encoding().transpose().map_err(|e| error(e.into()))?.map(|v| text(&v)).transpose()?,
+          standalone: e.standalone().transpose().map_err(|e| error(e.into()))?.map(|v| text(&v)).transpose()?,
+        };
+        self.tree.set_xml_decl(Some(decl));
+      }
+      _ => {} // I need to think about how i want to support some of the other nodes i have neglected here. 
+    }
+
+    self.record_span(event, reader);
+    Ok(())
+  }
 }
 
-/// Build an `XNode::Tag` from a `BytesStart` event, registering any
-/// xmlns declarations into the tree's namespace registry.
-fn build_tag<R: BufRead>(tree: &mut FlatTree, e: &BytesStart, reader: &Reader<R>) -> XNode {
+/// Build the node for a start tag, `resolver` has the tag's own scope pushed.
+///
+/// Element and attribute names are resolved to a uri in scope and the
+/// (prefix, uri) binding is registered, so a prefix redeclared further down
+/// gets an id of its own instead of reusing the first binding.
+fn build_tag<R: BufRead>(tree: &mut FlatTree, resolver: &NamespaceResolver, e: &BytesStart, reader: &Reader<R>, self_closing: bool, options: &ReadOptions) -> Result<XNode, ReadError> {
   let (local_name, prefix) = e.name().decompose();
-  let local = std::str::from_utf8(local_name.as_ref()).unwrap_or("");
-  let prefix_owned = prefix.map(|p| std::str::from_utf8(p.as_ref()).unwrap_or("").to_string());
+  let local = decode(reader, local_name.as_ref())?;
+  let element_prefix = match prefix {
+    Some(p) => decode(reader, p.into_inner())?,
+    None => Cow::Borrowed(""),
+  };
+  let qname = e.name();
+  let qname = decode(reader, qname.as_ref())?;
 
-  let decoder = reader.decoder();
   let mut attributes = BTreeMap::new();
-  let mut ns_id: Option<u16> = None;
-
-  for attr_result in e.attributes() {
-    let Ok(attr) = attr_result else { continue };
-    let key = std::str::from_utf8(attr.key.as_ref()).unwrap_or("");
-    let value = attr.decode_and_unescape_value(decoder).unwrap_or_default();
-
-    if key == "xmlns" {
-      ns_id = tree
-          .add_namespace("".into(), value.into_owned().into_boxed_str());
-    } else if let Some(ns_prefix) = key.strip_prefix("xmlns:") {
-      tree.add_namespace(
-        ns_prefix.to_string().into_boxed_str(),
-        value.into_owned().into_boxed_str(),
-      );
+
+  let mut pending: Vec<(String, String)> = Vec::new();
+  let mut positions: BTreeMap<String, usize> = BTreeMap::new();
+
+  // Repeated names are sorted out here rather than by quick_xml's check. Not
+  // strict, the last one wins, since for a declaration that is the binding
+  // the resolver sees.
+  for (count, attr_result) in e.attributes().with_checks(false).enumerate() {
+    if let Some(limit) = options.max_attributes_per_element && count >= limit {
+      return Err(ReadError::new(reader.buffer_position(), ReadErrorKind::TooManyAttributes { limit }));
+    }
+    let Ok(attr) = attr_result else {
+      recover(options, reader, RecoveryKind::MalformedAttribute);
+      continue;
+    };
+    let key = decode(reader, attr.key.as_ref())?;
+    let key = &*key;
+    let raw = decode(reader, &attr.value)?;
+    let kind = options.attribute_type.map_or(AttributeType::Cdata, |f| f(&qname, key));
+    let value = normalize_attribute_value(&raw, kind, options.resolve_entity)
+      .map_err(|e| ReadError::xml(reader.buffer_position(), e))?;
+
+    match positions.get(key) {
+      None => {
+        positions.insert(key.to_string(), pending.len());
+        pending.push((key.to_string(), value));
+      }
+      Some(_) if options.strict => {
+        return Err(ReadError::new(reader.buffer_position(), ReadErrorKind::DuplicateAttribute { name: key.into() }));
+      }
+      Some(&i) => {
+        pending[i].1 = value;
+        recover(options, reader, RecoveryKind::DuplicateAttribute { name: key.into() });
+      }
+    }
+  }
+
+  for (key, value) in &pending {
+    // `xmlns=""` undeclares the default namespace (and `xmlns:p=""` the
+    // prefix in XML 1.1), there is nothing to bind.
+    let declared = if key == "xmlns" { Some("") } else { key.strip_prefix("xmlns:") };
+    if let Some(ns_prefix) = declared && !value.is_empty() {
+      register_namespace(tree, ns_prefix, value, reader, options)?;
+    }
+  }
+
+  // Declarations are kept on the tag as attributes, and prefixes are resolved
+  // once all of them are registered since `a:attr` may come before `xmlns:a`.
+  let mut seen = BTreeSet::new();
+  for (key, value) in pending {
+    let (key, namespace) = if key == "xmlns" || key.starts_with("xmlns:") {
+      (key, None)
     } else {
-    let (prefix, name) = format_tag_name(key);
-      attributes.insert(
-        name.to_string().into_boxed_str(),
-        XAttribute {
-          namespace: tree.find_namespace(prefix),
-          value: value.into_owned().into_boxed_str(),
-        },
-      );
+      let (prefix, name) = split_qname(&key);
+      let (resolved, _) = resolver.resolve_attribute(QName(key.as_bytes()));
+      let namespace = resolve_namespace(tree, resolved, prefix.unwrap_or(""), reader, options)?;
+      let name = transform_name(name, options);
+      let key = match prefix {
+        Some(prefix) => format!("{prefix}:{name}"),
+        None => name.into_owned(),
+      };
+      (key, namespace)
+    };
+
+    if options.strict {
+      // Two prefixes bound to the same uri make the same name.
+      let identity = match tree.namespace_uri(namespace) {
+        Some(uri) => (Some(uri.to_string()), split_qname(&key).1.to_string()),
+        None => (None, key.clone()),
+      };
+      if !seen.insert(identity) {
+        return Err(ReadError::new(reader.buffer_position(), ReadErrorKind::DuplicateAttribute { name: key.into() }));
+      }
     }
+    attributes.insert(
+      key.into_boxed_str(),
+      XAttribute {
+        namespace,
+        value: value.into_boxed_str(),
+      },
+    );
   }
 
-  XNode::Tag {
-    namespace: ns_id.or(tree.find_namespace(prefix_owned.as_deref())),
-    name: local.to_string().into_boxed_str(),
+  let (resolved, _) = resolver.resolve_element(e.name());
+  let namespace = resolve_namespace(tree, resolved, &element_prefix, reader, options)?;
+
+  Ok(XNode::Tag {
+    namespace,
+    name: transform_name(&local, options).into(),
     attributes: if attributes.is_empty() {None} else {Some(attributes)},
+    self_closing,
+  })
+}
+
+/// `ReadOptions::name_transform` applied to a local name.
+fn transform_name<'a>(name: &'a str, options: &ReadOptions) -> Cow<'a, str> {
+  match options.name_transform {
+    Some(transform) => Cow::Owned(transform(name)),
+    None => Cow::Borrowed(name),
+  }
+}
+
+/// Attribute value normalization, done on the raw value so whitespace from
+/// character references survives it. An entity neither predefined nor known
+/// to `resolve_entity` is an error, like in text.
+pub(crate) fn normalize_attribute_value(
+  raw: &str,
+  kind: AttributeType,
+  resolve_entity: Option<fn(&str) -> Option<&'static str>>,
+) -> Result<String, EscapeError> {
+  let spaced = raw.replace("\r\n", " ").replace(['\t', '\n', '\r'], " ");
+  let value = unescape_with(&spaced, |name| {
+    resolve_predefined_entity(name).or_else(|| resolve_entity.and_then(|f| f(name)))
+  })?
+  .into_owned();
+
+  Ok(match kind {
+    AttributeType::Cdata => value,
+    AttributeType::Tokenized => value.split(' ').filter(|t| !t.is_empty()).collect::<Vec<_>>().join(" "),
+  })
+}
+
+/// The registry id for a name the resolver looked up. A prefix the resolver
+/// has never seen falls back to the registry, which may have been seeded.
+fn resolve_namespace<R: BufRead>(tree: &mut FlatTree, resolved: ResolveResult, prefix: &str, reader: &Reader<R>, options: &ReadOptions) -> Result<Option<u16>, ReadError> {
+  match resolved {
+    ResolveResult::Bound(ns) => {
+      let uri = decode(reader, ns.as_ref())?;
+      register_namespace(tree, prefix, &uri, reader, options).map(Some)
+    }
+    ResolveResult::Unbound => Ok(None),
+    ResolveResult::Unknown(_) => Ok(tree.find_namespace(Some(prefix))),
+  }
+}
+
+/// Copy the namespace declarations of the document element of `root` onto the
+/// first node of `record`, keeping the record's own. `xmlns=""` has nothing to
+/// inherit.
+fn inherit_declarations(root: &FlatTree, record: &mut FlatTree) {
+  let Some(XNode::Tag { attributes: Some(declared), .. }) = root.document_element().and_then(|e| e.value(root)) else {
+    return;
+  };
+  let Some(XNode::Tag { attributes, .. }) = record.value_mut(0) else {
+    return;
+  };
+
+  for (key, attr) in declared {
+    if (&**key == "xmlns" || key.starts_with("xmlns:")) && !attr.value.is_empty() {
+      attributes.get_or_insert_with(BTreeMap::new).entry(key.clone()).or_insert_with(|| attr.clone());
+    }
+  }
+}
+
+/// Decode names and other raw bytes of the input with the reader's decoder,
+/// the same one text goes through, so they follow the declared encoding.
+fn decode<'b, R>(reader: &Reader<R>, bytes: &'b [u8]) -> Result<Cow<'b, str>, ReadError> {
+  reader.decoder().decode(bytes).map_err(|e| ReadError::xml(reader.buffer_position(), e))
+}
+
+/// Report a `RecoveryEvent` to `ReadOptions::on_recover`, if set.
+fn recover<R>(options: &ReadOptions, reader: &Reader<R>, kind: RecoveryKind) {
+  if let Some(on_recover) = options.on_recover {
+    on_recover(RecoveryEvent { position: reader.buffer_position(), kind });
+  }
+}
+
+/// Register a declared namespace, failing rather than leaving the tag
+/// unbound when the registry can not take another prefix.
+fn register_namespace<R: BufRead>(tree: &mut FlatTree, prefix: &str, uri: &str, reader: &Reader<R>, options: &ReadOptions) -> Result<u16, ReadError> {
+  if let Some(id) = tree.find_namespace_binding(prefix, uri) {
+    return Ok(id);
+  }
+
+  let full = || ReadError::new(reader.buffer_position(), ReadErrorKind::NamespaceRegistryFull { prefix: prefix.into() });
+  if options.max_namespaces.is_some_and(|max| tree.namespace_count() >= max) {
+    return Err(full());
+  }
+  tree.add_namespace(prefix.into(), uri.into()).ok_or_else(full)
+}
+
+/// Only XML whitespace, which is narrower than `char::is_whitespace`.
+fn is_whitespace(text: &str) -> bool {
+  text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+}
+
+/// `Some(true)` for `xml:space="preserve"`, `Some(false)` for `"default"`,
+/// `None` when the tag does not set it and the parent's value applies.
+fn xml_space(xnode: &XNode) -> Option<bool> {
+  let XNode::Tag { attributes: Some(attributes), .. } = xnode else {
+    return None;
+  };
+
+  match attributes.get("xml:space").map(|a| &*a.value) {
+    Some("preserve") => Some(true),
+    Some("default") => Some(false),
+    _ => None,
   }
 }
 
-fn format_tag_name(key: &str) -> (Option<&str>, &str){
-  let mut split = key.split(':');
+/// Push text under `parent`, merging it into the previous node when that is
+/// text as well. quick_xml splits `a&amp;b` into text, reference and text
+/// events but it is one run of text.
+fn push_text(tree: &mut FlatTree, parent: &Node, text: &str) {
+  let depth = parent.depth(tree) + 1;
+  let last = tree.len().wrapping_sub(1);
 
-  let prefix = split.next();
-  let name = split.next();
+  if tree.node(last).is_some_and(|n| n.depth(tree) == depth)
+    && let Some(XNode::Text(previous)) = tree.value_mut(last)
+  {
+    *previous = [&**previous, text].concat().into_boxed_str();
+    return;
+  }
+
+  _ = parent.push(tree, XNode::Text(text.into()));
+}
 
-  if name.is_none(){
-    return (None, prefix.unwrap());
+/// Resolve a character reference or one of the predefined entities.
+pub(crate) fn resolve_reference(e: &BytesRef) -> Result<String, quick_xml::Error> {
+  if let Some(ch) = e.resolve_char_ref()? {
+    return Ok(ch.to_string());
   }
 
-  (prefix, name.unwrap())
+  let name = e.decode()?;
+  match resolve_predefined_entity(&name) {
+    Some(value) => Ok(value.to_string()),
+    None => Err(EscapeError::UnrecognizedEntity(0..name.len(), name.into_owned()).into()),
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
   use quick_xml::Reader;
+  use xml_tree::canonical_attributes;
 
   #[test]
   fn read_simple_xml() {
@@ -183,6 +843,488 @@ mod tests {
     assert!(matches!(tree.value(3), Some(XNode::Comment(c)) if &**c == " comment "));
   }
 
+  #[test]
+  fn read_from_str() {
+    let tree = read_str("<root><a/></root>").unwrap();
+
+    assert_eq!(tree.depth_vector(), [1, 2]);
+    assert!(read_str("<root><a></root>").is_err());
+  }
+
+  #[test]
+  fn read_skips_leading_whitespace_and_bom() {
+    let tree = read_str("\u{feff}  \n<root>  <a/></root>\n").unwrap();
+    assert_eq!(tree.depth_vector(), [1, 2, 2]);
+    assert!(matches!(tree.value(0), Some(XNode::Tag { name, .. }) if &**name == "root"));
+
+    // A BOM quick_xml did not strip itself, the reader was already past the start.
+    let mut reader = Reader::from_str("<?xml version=\"1.0\"?>\u{feff}\n<root/>");
+    let mut buf = Vec::new();
+    assert!(matches!(reader.read_event_into(&mut buf).unwrap(), Event::Decl(_)));
+
+    let tree = read(reader, &mut buf).unwrap();
+    assert_eq!(tree.len(), 1);
+    assert!(matches!(tree.value(0), Some(XNode::Tag { name, .. }) if &**name == "root"));
+  }
+
+  #[test]
+  fn read_fragment_forest() {
+    let tree = read_fragment(Reader::from_str("<a/><b/>"), &mut Vec::new()).unwrap();
+    assert_eq!(tree.depth_vector(), [1, 1]);
+    let names: Vec<_> = tree
+      .top_level_nodes()
+      .filter_map(|n| match n.value(&tree) {
+        Some(XNode::Tag { name, .. }) => Some(name.to_string()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(names, ["a", "b"]);
+
+    // Whitespace between fragment roots is content, for a document it is not.
+    let tree = read_fragment(Reader::from_str("<a/> <b/>"), &mut Vec::new()).unwrap();
+    assert!(matches!(tree.value(1), Some(XNode::Text(text)) if &**text == " "));
+    assert_eq!(read_str("<a/> <b/>").unwrap().len(), 2);
+  }
+
+  #[test]
+  fn read_source_spans() {
+    let xml = "<root>\n  <child a=\"1\">x &amp; y</child><!--c--><e/>\n</root>";
+    let options = ReadOptions { record_spans: true, trim_whitespace: true, ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+    let source = |index: usize| {
+      let (start, end) = tree.node(index).unwrap().source_span(&tree).unwrap();
+      &xml[start..end]
+    };
+
+    assert_eq!(tree.len(), 5);
+    assert_eq!(source(0), xml);
+    assert_eq!(source(1), "<child a=\"1\">x &amp; y</child>");
+    assert_eq!(source(2), "x &amp; y");
+    assert_eq!(source(3), "<!--c-->");
+    assert_eq!(source(4), "<e/>");
+
+    let plain = read_str(xml).unwrap();
+    assert!(plain.node(0).unwrap().source_span(&plain).is_none());
+  }
+
+  #[test]
+  fn read_with_name_transform() {
+    let xml = r#"<Root xmlns:P="urn:p" ID="1" P:Lang="en"><P:Item/></Root>"#;
+    let options = ReadOptions { name_transform: Some(str::to_lowercase), ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+
+    let Some(XNode::Tag { name, attributes: Some(attributes), .. }) = tree.value(0) else {
+      panic!("expected the root tag");
+    };
+    assert_eq!(&**name, "root");
+    let keys: Vec<&str> = attributes.keys().map(|k| &**k).collect();
+    assert_eq!(keys, ["P:lang", "id", "xmlns:P"]);
+    assert!(tree.find_node("P:item").is_some());
+  }
+
+  #[test]
+  fn attribute_value_normalization() {
+    let xml = "<root a=\"x  y\tz\r\n&#xA;\" list=\"  x  y\tz \"/>";
+    let value = |tree: &FlatTree, key: &str| match tree.value(0) {
+      Some(XNode::Tag { attributes: Some(attributes), .. }) => attributes[key].value.to_string(),
+      _ => panic!("expected a tag"),
+    };
+
+    let tree = read_str(xml).unwrap();
+    assert_eq!(value(&tree, "a"), "x  y z \n");
+    assert_eq!(value(&tree, "list"), "  x  y z ");
+
+    fn schema(element: &str, attribute: &str) -> AttributeType {
+      match (element, attribute) {
+        ("root", "list") => AttributeType::Tokenized,
+        _ => AttributeType::Cdata,
+      }
+    }
+    let options = ReadOptions { attribute_type: Some(schema), ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+    assert_eq!(value(&tree, "a"), "x  y z \n");
+    assert_eq!(value(&tree, "list"), "x y z");
+  }
+
+  #[test]
+  fn unknown_entities() {
+    // In an attribute value the same as in text, not an empty value.
+    for xml in [r#"<a v="x &bogus; y"/>"#, "<a>x &bogus; y</a>"] {
+      let error = read_str(xml).unwrap_err();
+      assert!(matches!(error.kind, ReadErrorKind::Xml(quick_xml::Error::Escape(_))), "{xml}");
+    }
+
+    fn entity(name: &str) -> Option<&'static str> {
+      (name == "bogus").then_some("&amp;")
+    }
+    let options = ReadOptions { resolve_entity: Some(entity), ..ReadOptions::default() };
+    let xml = r#"<a v="x &bogus; &amp; y">x &bogus; &unknown; y</a>"#;
+    let error = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap_err();
+    assert!(matches!(error.kind, ReadErrorKind::Xml(quick_xml::Error::Escape(_))));
+
+    // The replacement text is taken as is.
+    let tree = read_with(Reader::from_str(r#"<a v="x &bogus; &amp; y">x &bogus; y</a>"#), &mut Vec::new(), &options).unwrap();
+    assert_eq!(
+      xml_tree::canonicalize(&tree, &tree.document()),
+      r#"<a v="x &amp;amp; &amp; y">x &amp;amp; y</a>"#
+    );
+  }
+
+  #[test]
+  fn canonical_declaration_order() {
+    // e5 from the C14N spec, example 3.3.
+    let xml = r#"<e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+  xmlns:b="http://www.ietf.org"
+  xmlns:a="http://www.w3.org"
+  xmlns="http://example.org"/>"#;
+    let expected = r#"<e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>"#;
+
+    let tree = read_str(xml).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), expected);
+
+    let mut writer = crate::canonical_writer::CanonicalWriter::new(Vec::new());
+    writer.write_reader(Reader::from_str(xml), &mut Vec::new()).unwrap();
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+  }
+
+  #[test]
+  fn canonical_default_undeclaration() {
+    // The e6 to e9 chain from the C14N spec, example 3.3.
+    let xml = r#"<doc><e5 xmlns="http://example.org"/><e6 xmlns="" xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns="" xmlns:a="http://www.w3.org"><e9 xmlns="" xmlns:a="http://www.ietf.org"/></e8></e7></e6></doc>"#;
+    let tree = read_str(xml).unwrap();
+
+    assert_eq!(tree.namespace_count(), 4);
+    assert_eq!(
+      xml_tree::canonicalize(&tree, &tree.document()),
+      concat!(
+        r#"<doc><e5 xmlns="http://example.org"></e5>"#,
+        r#"<e6 xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns="">"#,
+        r#"<e9 xmlns:a="http://www.ietf.org"></e9></e8></e7></e6></doc>"#,
+      )
+    );
+
+    // A subtree under an undeclared default has nothing to undeclare.
+    let e9 = tree.find_node_by_name("e9").unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &e9), r#"<e9 xmlns:a="http://www.ietf.org"></e9>"#);
+    let e8 = tree.find_node_by_name("e8").unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &e8), r#"<e8 xmlns:a="http://www.w3.org"><e9 xmlns:a="http://www.ietf.org"></e9></e8>"#);
+  }
+
+  #[test]
+  fn read_cdata_and_pi_data() {
+    let tree = read_str("<a>x<![CDATA[<y> & z]]>w<?pi  data  ?></a>").unwrap();
+    assert_eq!(tree.depth_vector(), [1, 2, 2]);
+    assert!(matches!(tree.value(1), Some(XNode::Text(text)) if &**text == "x<y> & zw"));
+    assert!(matches!(tree.value(2), Some(XNode::ProcessingInstruction { data: Some(data), .. }) if &**data == "data  "));
+  }
+
+  #[test]
+  fn recovery_events() {
+    use std::cell::RefCell;
+
+    thread_local! {
+      static EVENTS: RefCell<Vec<RecoveryEvent>> = const { RefCell::new(Vec::new()) };
+    }
+    fn record(event: RecoveryEvent) {
+      EVENTS.with_borrow_mut(|events| events.push(event));
+    }
+
+    let options = ReadOptions { on_recover: Some(record), ..ReadOptions::default() };
+    let mut reader = Reader::from_str(r#"<root><a x="1" x="2"/></stray></root>"#);
+    reader.config_mut().check_end_names = false;
+    reader.config_mut().allow_unmatched_ends = true;
+    let tree = read_with(reader, &mut Vec::new(), &options).unwrap();
+    assert_eq!(tree.depth_vector(), [1, 2]);
+
+    let events = EVENTS.take();
+    assert_eq!(
+      events,
+      [
+        RecoveryEvent { position: 22, kind: RecoveryKind::DuplicateAttribute { name: "x".into() } },
+        RecoveryEvent { position: 30, kind: RecoveryKind::StrayEndTag { name: "stray".into() } },
+      ]
+    );
+
+    // Nothing to recover from, nothing reported.
+    read_with(Reader::from_str("<root/>"), &mut Vec::new(), &options).unwrap();
+    assert!(EVENTS.take().is_empty());
+  }
+
+  #[test]
+  fn whitespace_nodes() {
+    let xml = "<root>\n  <a>x</a>\n  <b> </b><c>  y  </c>\n</root>";
+    let options = ReadOptions { whitespace_nodes: true, ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+
+    let kinds: Vec<&str> = tree
+      .iter()
+      .map(|node| match node.value(&tree) {
+        Some(XNode::Tag { .. }) => "tag",
+        Some(XNode::Whitespace(_)) => "ws",
+        Some(XNode::Text(_)) => "text",
+        _ => "other",
+      })
+      .collect();
+    assert_eq!(kinds, ["tag", "ws", "tag", "text", "ws", "tag", "ws", "tag", "text", "ws"]);
+    assert!(matches!(tree.value(1), Some(XNode::Whitespace(text)) if &**text == "\n  "));
+
+    // Only the node kind changes.
+    let plain = read_str(xml).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), xml_tree::canonicalize(&plain, &plain.document()));
+    assert_eq!(tree.node(0).unwrap().text_content(&tree), plain.node(0).unwrap().text_content(&plain));
+
+    let trimmed = ReadOptions { trim_whitespace: true, ..options };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &trimmed).unwrap();
+    assert!(tree.iter().all(|node| !matches!(node.value(&tree), Some(XNode::Whitespace(_)))));
+  }
+
+  #[test]
+  fn reserved_xml_prefix() {
+    let tree = read_str(r#"<e xml:lang="en"><f xml:space="preserve"/></e>"#).unwrap();
+    for (index, key) in [(0, "xml:lang"), (1, "xml:space")] {
+      let Some(XNode::Tag { attributes: Some(attributes), .. }) = tree.value(index) else { panic!() };
+      assert_eq!(tree.namespace_uri(attributes[key].namespace), Some(xml_tree::XML_NAMESPACE));
+    }
+    assert_eq!(tree.namespace_count(), 1);
+    // Bound, but never declared.
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.node(1).unwrap()), r#"<f xml:lang="en" xml:space="preserve"></f>"#);
+  }
+
+  #[test]
+  fn keep_pi_whitespace() {
+    let xml = "<a><?pi   data  x ?><?empty   ?><?bare?></a>";
+    let options = ReadOptions { keep_pi_whitespace: true, ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+    assert!(matches!(tree.value(1), Some(XNode::ProcessingInstruction { data: Some(data), .. }) if &**data == "   data  x "));
+
+    let mut writer = quick_xml::Writer::new(Vec::new());
+    crate::quick_writer::write(&mut writer, &tree).unwrap();
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), xml);
+
+    let canonical = "<a><?pi data  x ?><?empty?><?bare?></a>";
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), canonical);
+    assert_eq!(xml_tree::canonicalize(&read_str(xml).unwrap(), &tree.document()), canonical);
+    assert_eq!(tree.logical_hash(), read_str(xml).unwrap().logical_hash());
+  }
+
+  #[test]
+  fn read_xml_declaration() {
+    let tree = read_str(r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?><root/>"#).unwrap();
+    let decl = tree.xml_decl().unwrap();
+    assert_eq!(&*decl.version, "1.0");
+    assert_eq!(decl.encoding.as_deref(), Some("UTF-8"));
+    assert_eq!(decl.standalone.as_deref(), Some("no"));
+    // Not a node, and not a PI.
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree.node_counts().pis, 0);
+
+    let tree = read_str(r#"<?xml version='1.1'?><root/>"#).unwrap();
+    assert_eq!(tree.xml_decl(), Some(&XmlDecl { version: "1.1".into(), encoding: None, standalone: None }));
+    assert!(read_str("<root/>").unwrap().xml_decl().is_none());
+
+    assert!(read_str(r#"<?xml encoding="UTF-8"?><root/>"#).is_err());
+  }
+
+  #[test]
+  fn read_declared_encoding() {
+    // Names, attributes, PIs and text all go through the decoder the
+    // declaration picks.
+    let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><caf\xE9 \xE9t\xE9=\"\xE9\"><?p\xEF d\xE0?>\xE9</caf\xE9>";
+    let tree = read(Reader::from_reader(&latin1[..]), &mut Vec::new()).unwrap();
+    assert_eq!(tree.xml_decl().unwrap().encoding.as_deref(), Some("ISO-8859-1"));
+
+    let root = tree.document_element().unwrap();
+    assert!(matches!(root.value(&tree), Some(XNode::Tag { name, .. }) if &**name == "café"));
+    assert_eq!(root.attributes_as(&tree, &["été"]), [Some("é")]);
+    assert_eq!(root.text_content(&tree), "é");
+
+    // Canonical output is always UTF-8.
+    let canonical = xml_tree::canonicalize(&tree, &tree.document());
+    assert_eq!(canonical.as_bytes(), "<café été=\"é\"><?pï dà?>é</café>".as_bytes());
+    assert_eq!(canonical, xml_tree::canonicalize(&read_str("<café été=\"é\"><?pï dà?>é</café>").unwrap(), &tree.document()));
+
+    // The streaming writer decodes the same way, namespaces included.
+    let namespaced = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><\xE9:a xmlns:\xE9=\"urn:\xE9\" \xE9:\xE9=\"\xE9\" b=\"\xE0\"/>";
+    for xml in [&latin1[..], &namespaced[..]] {
+      let tree = read(Reader::from_reader(xml), &mut Vec::new()).unwrap();
+      let mut writer = crate::canonical_writer::CanonicalWriter::new(Vec::new());
+      writer.write_reader(Reader::from_reader(xml), &mut Vec::new()).unwrap();
+      assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), xml_tree::canonicalize(&tree, &tree.document()));
+    }
+  }
+
+  #[test]
+  fn read_concatenated_documents() {
+    let xml = "<doc/>\n  <doc><a>1</a></doc>\n<?xml version=\"1.0\"?><!--c--><doc>x</doc>\n";
+    let trees: Vec<FlatTree> = read_stream(Reader::from_str(xml), &mut Vec::new())
+      .collect::<Result<_, _>>()
+      .unwrap();
+
+    assert_eq!(trees.len(), 3);
+    assert_eq!(trees[0].depth_vector(), [1]);
+    assert_eq!(trees[1].depth_vector(), [1, 2, 3]);
+    assert_eq!(trees[2].depth_vector(), [1, 1, 2]);
+    assert!(trees[2].xml_decl().is_some());
+
+    let mut buf = Vec::new();
+    let mut stream = read_stream(Reader::from_str("<doc/><doc></a>"), &mut buf);
+    assert!(stream.next().unwrap().is_ok());
+    assert!(stream.next().unwrap().is_err());
+    assert!(stream.next().is_none());
+  }
+
+  #[test]
+  fn duplicate_declarations() {
+    let xml = r#"<e xmlns:a="u1" xmlns:a="u2" a:x="1"><a:f/></e>"#;
+
+    let strict = ReadOptions { strict: true, ..ReadOptions::default() };
+    let error = read_with(Reader::from_str(xml), &mut Vec::new(), &strict).unwrap_err();
+    assert!(matches!(&error.kind, ReadErrorKind::DuplicateAttribute { name } if &**name == "xmlns:a"));
+
+    // Not strict the last declaration is the binding, for the names in
+    // scope and for the declaration kept on the tag alike.
+    let tree = read_str(xml).unwrap();
+    assert_eq!(tree.namespaces().collect::<Vec<_>>(), [(0, "a", "u2")]);
+    let f = tree.find_node_by_name("f").unwrap();
+    assert!(matches!(f.value(&tree), Some(XNode::Tag { namespace: Some(0), .. })));
+    assert_eq!(
+      xml_tree::canonicalize(&tree, &tree.document()),
+      r#"<e xmlns:a="u2" a:x="1"><a:f></a:f></e>"#
+    );
+  }
+
+  #[test]
+  fn strict_duplicate_attributes() {
+    let strict = ReadOptions { strict: true, ..ReadOptions::default() };
+    let read_strict = |xml: &str| read_with(Reader::from_str(xml), &mut Vec::new(), &strict);
+
+    let error = read_strict(r#"<e attr="1" attr="2"/>"#).unwrap_err();
+    assert!(matches!(&error.kind, ReadErrorKind::DuplicateAttribute { name } if &**name == "attr"));
+
+    let error = read_strict(r#"<e xmlns:a="urn:x" xmlns:b="urn:x" a:attr="1" b:attr="2"/>"#).unwrap_err();
+    assert!(matches!(&error.kind, ReadErrorKind::DuplicateAttribute { name } if &**name == "b:attr"));
+
+    assert!(read_strict(r#"<e xmlns:a="urn:a" attr="1" a:attr="2"/>"#).is_ok());
+
+    // Not strict, the last one is kept, the same as for declarations.
+    let tree = read_str(r#"<e attr="1" attr="2"/>"#).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), r#"<e attr="2"></e>"#);
+    let tree = read_str(r#"<e xmlns:a="u1" a:x="1" xmlns:a="u2" a:x="2"/>"#).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), r#"<e xmlns:a="u2" a:x="2"></e>"#);
+  }
+
+  #[test]
+  fn strict_empty_input() {
+    let strict = ReadOptions { strict: true, ..ReadOptions::default() };
+    let read_strict = |xml: &str| read_with(Reader::from_str(xml), &mut Vec::new(), &strict);
+
+    let error = read_strict("").unwrap_err();
+    assert!(matches!(error.kind, ReadErrorKind::NoRootElement));
+    let error = read_strict(r#"<?xml version="1.0"?> <!--only a comment-->"#).unwrap_err();
+    assert!(matches!(error.kind, ReadErrorKind::NoRootElement));
+    assert_eq!(error.position, 43);
+    assert!(read_strict("<root/>").is_ok());
+
+    assert!(read_str("").unwrap().is_empty());
+    assert!(read_with(Reader::from_str(""), &mut Vec::new(), &ReadOptions::default()).unwrap().is_empty());
+  }
+
+  #[test]
+  fn attribute_limit() {
+    let options = ReadOptions { max_attributes_per_element: Some(2), ..ReadOptions::default() };
+    let read_limited = |xml: &str| read_with(Reader::from_str(xml), &mut Vec::new(), &options);
+
+    assert!(read_limited(r#"<root a="1" b="2"><e c="3"/></root>"#).is_ok());
+    let error = read_limited(r#"<root a="1"><e xmlns:p="urn:p" b="2" c="3"/></root>"#).unwrap_err();
+    assert!(matches!(error.kind, ReadErrorKind::TooManyAttributes { limit: 2 }));
+    assert!(read_limited(r#"<e a="1" a="2" a="3"/>"#).is_err());
+  }
+
+  #[test]
+  fn namespace_registry_full() {
+    let xml = r#"<root xmlns:a="urn:a"><b:x xmlns:b="urn:b"/></root>"#;
+    let options = ReadOptions { max_namespaces: Some(1), ..ReadOptions::default() };
+    let error = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap_err();
+    assert!(matches!(&error.kind, ReadErrorKind::NamespaceRegistryFull { prefix } if &**prefix == "b"));
+
+    // Redeclaring a registered prefix does not need a new slot.
+    let xml = r#"<root xmlns:a="urn:a"><a:x xmlns:a="urn:a"/></root>"#;
+    assert!(read_with(Reader::from_str(xml), &mut Vec::new(), &options).is_ok());
+
+    // Without a limit the u16 ids run out instead.
+    let mut tree = FlatTree::new();
+    for i in 0..=u16::MAX {
+      tree.add_namespace(format!("p{i}").into(), "urn:p".into()).unwrap();
+    }
+    let mut state = TreeState::new(tree);
+    let mut reader = Reader::from_str(r#"<x xmlns:full="urn:full"/>"#);
+    let event = reader.read_event().unwrap();
+    let error = state.handle(&event, &reader, &ReadOptions::default()).unwrap_err();
+    assert!(matches!(&error.kind, ReadErrorKind::NamespaceRegistryFull { prefix } if &**prefix == "full"));
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn read_many_matches_sequential() {
+    let documents: Vec<String> = (0..64)
+      .map(|i| format!("<doc n=\"{i}\">{}</doc>", "<item/>".repeat(i)))
+      .chain(["<broken></doc>".to_string()])
+      .collect();
+    let inputs: Vec<&[u8]> = documents.iter().map(|d| d.as_bytes()).collect();
+
+    let parallel = read_many(inputs.clone());
+    assert_eq!(parallel.len(), inputs.len());
+    for (input, result) in inputs.iter().zip(&parallel) {
+      let sequential = read(Reader::from_reader(*input), &mut Vec::new());
+      match (result, sequential) {
+        (Ok(tree), Ok(expected)) => assert_eq!(tree.depth_vector(), expected.depth_vector()),
+        (Err(error), Err(expected)) => assert_eq!(error.position, expected.position),
+        _ => panic!("parallel and sequential results differ"),
+      }
+    }
+    assert!(parallel.last().unwrap().is_err());
+
+    // An unindexed source keeps its order as well.
+    use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+    let even = |input: &&[u8]| input.len().is_multiple_of(2);
+    let parallel = read_many(inputs.par_iter().copied().filter(even));
+    let sequential: Vec<_> = inputs.iter().filter(|input| even(input)).map(|input| read(Reader::from_reader(*input), &mut Vec::new())).collect();
+    assert!(sequential.len() > 1);
+    let depths = |results: &[Result<FlatTree, ReadError>]| -> Vec<_> { results.iter().map(|r| r.as_ref().ok().map(|tree| tree.depth_vector())).collect() };
+    assert_eq!(depths(&parallel), depths(&sequential));
+  }
+
+  #[cfg(feature = "flate2")]
+  #[test]
+  fn read_gzip_document() {
+    use std::io::Write;
+
+    let xml = "<root><child a=\"1\">text</child></root>";
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(xml.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let tree = read_gzip(compressed.as_slice()).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), xml);
+
+    let error = read_gzip(xml.as_bytes()).unwrap_err();
+    assert!(matches!(error.kind, ReadErrorKind::Xml(quick_xml::Error::Io(_))));
+  }
+
+  #[test]
+  fn read_without_comments() {
+    let xml = "<!--head--><root>a<!-- one -->b<child><!-- two --></child></root>";
+    let options = ReadOptions { keep_comments: false, ..ReadOptions::default() };
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+
+    assert_eq!(tree.node_counts().comments, 0);
+    assert_eq!(tree.depth_vector(), [1, 2, 2]);
+    assert!(matches!(tree.value(1), Some(XNode::Text(text)) if &**text == "ab"));
+
+    let tree = read_with(Reader::from_str(xml), &mut Vec::new(), &ReadOptions::default()).unwrap();
+    assert_eq!(tree.node_counts().comments, 3);
+  }
+
   #[test]
   fn read_with_namespaces() {
     let xml = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body/></soap:Envelope>"#;
@@ -244,6 +1386,15 @@ mod tests {
     let has_pi = (0..tree.len())
       .any(|i| matches!(tree.value(i), Some(XNode::ProcessingInstruction { .. })));
     assert!(has_pi);
+
+    assert_eq!(
+      xml_tree::canonicalize(&tree, &tree.document()),
+      "<?xml-stylesheet href=\"style.css\"?>\n<root></root>"
+    );
+
+    let tree = read_str("<root><?empty ?></root><?tail?>").unwrap();
+    assert!(matches!(tree.value(1), Some(XNode::ProcessingInstruction { data: None, .. })));
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), "<root><?empty?></root>\n<?tail?>");
   }
 
   #[test]
@@ -262,14 +1413,14 @@ mod tests {
     let node = node.unwrap();
 
     match node {
-        XNode::Tag { namespace, name: _, attributes } => {
+        XNode::Tag { namespace, name: _, attributes, .. } => {
           assert!(namespace.is_some());
           assert_eq!(namespace.unwrap(), 0);
 
           assert!(attributes.is_some());
           let attributes = attributes.as_ref().unwrap();
 
-          let attr = attributes.get("attr");
+          let attr = attributes.get("ns:attr");
 
           assert!(attr.is_some());
           let attr = attr.unwrap();
@@ -279,6 +1430,161 @@ mod tests {
     }
   }
 
+  #[test]
+  fn read_canonical_attribute_order() {
+    let xml = r#"<e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+  xmlns:b="http://www.ietf.org"
+  xmlns:a="http://www.w3.org"
+  xmlns="http://example.org"/>"#;
+    let reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+
+    let tree = read(reader, &mut buf).unwrap();
+    let e5 = tree.node(0).unwrap();
+
+    let keys: Vec<&str> = canonical_attributes(&tree, &e5)
+      .into_iter()
+      .map(|(key, _)| key)
+      .collect();
+
+    // Declarations by prefix, then attributes by (namespace uri, local name).
+    assert_eq!(
+      keys,
+      ["xmlns", "xmlns:a", "xmlns:b", "attr", "attr2", "b:attr", "a:attr"]
+    );
+  }
+
+  #[test]
+  fn read_coalesces_references() {
+    let xml = r#"<root>a&amp;b&#x21;</root>"#;
+    let reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+
+    let tree = read(reader, &mut buf).unwrap();
+
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree.depth_vector(), [1, 2]);
+    assert!(matches!(tree.value(1), Some(XNode::Text(t)) if &**t == "a&b!"));
+  }
+
+  #[test]
+  fn read_unknown_entity() {
+    let reader = Reader::from_str("<root>&custom;</root>");
+    let mut buf = Vec::new();
+
+    assert!(read(reader, &mut buf).is_err());
+  }
+
+  #[test]
+  fn read_with_trim_text() {
+    let xml = "<root>\n  <a> x </a>\n  <b/>\n</root>";
+    let options = ReadOptions {
+      trim_text: true,
+      ..ReadOptions::default()
+    };
+    let mut buf = Vec::new();
+
+    let tree = read_with(Reader::from_str(xml), &mut buf, &options).unwrap();
+
+    assert_eq!(tree.len(), 4);
+    assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+    assert!(matches!(tree.value(2), Some(XNode::Text(t)) if &**t == "x"));
+  }
+
+  #[test]
+  fn read_trim_whitespace_honors_xml_space() {
+    let xml = "<root>\n  <code xml:space=\"preserve\">\n  <a/>  <b xml:space=\"default\"> <c/> </b></code>\n  <text> <a/> x </text>\n</root>";
+    let options = ReadOptions {
+      trim_whitespace: true,
+      ..ReadOptions::default()
+    };
+    let mut buf = Vec::new();
+
+    let tree = read_with(Reader::from_str(xml), &mut buf, &options).unwrap();
+
+    // root, code, "\n  ", a, "  ", b, c, text, a, " x "
+    assert_eq!(tree.depth_vector(), [1, 2, 3, 3, 3, 3, 4, 2, 3, 3]);
+    assert!(matches!(tree.value(2), Some(XNode::Text(t)) if &**t == "\n  "));
+    assert!(matches!(tree.value(4), Some(XNode::Text(t)) if &**t == "  "));
+    assert!(matches!(tree.value(9), Some(XNode::Text(t)) if &**t == " x "));
+  }
+
+  #[test]
+  fn read_with_expand_empty_elements() {
+    let xml = "<root><a/></root>";
+    let options = ReadOptions {
+      expand_empty_elements: true,
+      ..ReadOptions::default()
+    };
+    let mut buf = Vec::new();
+
+    let expanded = read_with(Reader::from_str(xml), &mut buf, &options).unwrap();
+    let plain = read(Reader::from_str(xml), &mut buf).unwrap();
+
+    assert_eq!(expanded.depth_vector(), plain.depth_vector());
+    assert!(matches!(expanded.value(1), Some(XNode::Tag { self_closing: false, .. })));
+    assert!(matches!(plain.value(1), Some(XNode::Tag { self_closing: true, .. })));
+  }
+
+  #[test]
+  fn read_error_position() {
+    let xml = "<root>\n  <a></b>\n</root>";
+    let reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+
+    let error = read(reader, &mut buf).unwrap_err();
+
+    // The mismatched `</b>` starts at byte 12, on line 2.
+    assert_eq!(error.position, 12);
+    assert_eq!(error.line_column(xml.as_bytes()), (2, 6));
+    assert!(error.to_string().contains("at byte 12"));
+  }
+
+  #[test]
+  fn read_records_one_at_a_time() {
+    let xml = r#"<root xmlns:r="urn:r"><record id="1"/><other><record/></other><record id="2"><r:x>a</r:x></record>text<record>3</record></root>"#;
+    let mut buf = Vec::new();
+    let mut records = Vec::new();
+
+    read_records(Reader::from_str(xml), &mut buf, "record", |tree| records.push(tree)).unwrap();
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].depth_vector(), [1]);
+    assert_eq!(records[1].depth_vector(), [1, 2, 3]);
+    assert_eq!(records[2].depth_vector(), [1, 2]);
+
+    // The root's namespaces carry over into the record trees.
+    let r = records[1].find_namespace(Some("r"));
+    assert!(r.is_some());
+    assert!(matches!(records[1].value(1), Some(XNode::Tag { namespace, .. }) if *namespace == r));
+    assert_eq!(
+      xml_tree::canonicalize(&records[1], &records[1].document()),
+      r#"<record xmlns:r="urn:r" id="2"><r:x>a</r:x></record>"#
+    );
+  }
+
+  #[test]
+  fn read_records_declare_inherited_namespaces() {
+    let xml = r#"<p:root xmlns:p="urn:p" xmlns="urn:d" xmlns:q="urn:q"><p:item a="1"/><p:item xmlns:q="urn:other"><q:x/></p:item></p:root>"#;
+    let mut records = Vec::new();
+    read_records(Reader::from_str(xml), &mut Vec::new(), "item", |tree| records.push(tree)).unwrap();
+
+    let canonical: Vec<String> = records.iter().map(|tree| xml_tree::canonicalize(tree, &tree.document())).collect();
+    assert_eq!(
+      canonical,
+      [
+        r#"<p:item xmlns="urn:d" xmlns:p="urn:p" xmlns:q="urn:q" a="1"></p:item>"#,
+        // Its own declaration wins over the inherited one.
+        r#"<p:item xmlns="urn:d" xmlns:p="urn:p" xmlns:q="urn:other"><q:x></q:x></p:item>"#,
+      ]
+    );
+    // Each one stands on its own.
+    for canonical in &canonical {
+      let tree = read_str(canonical).unwrap();
+      assert_eq!(&xml_tree::canonicalize(&tree, &tree.document()), canonical);
+    }
+  }
+
   /*#[test] Turns out quick_xml returns an error when this happens... Sadness.
   fn read_broken_xml() {
     let xml = r#"<root><e1><e2></e1></root>"#;
@@ -292,7 +1598,6 @@ mod tests {
     assert_eq!(tree.depth_vector(), [1, 2]);
   }*/
 
-  /* 
   #[test]
   fn advanced_xml_test() {
     let xml = r#"<!DOCTYPE doc [<!ATTLIST e9 attr CDATA "default">]>
@@ -314,14 +1619,40 @@ mod tests {
 </e6>
 </doc>"#;
 
-    let reader = Reader::from_str(xml);
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
     let mut buf = Vec::new();
 
     let tree = read(reader, &mut buf).unwrap();
 
-    print!("{}", tree.len());
+    // doc and e1 to e9, the doctype is not a node.
+    assert_eq!(tree.len(), 10);
+    assert_eq!(tree.depth_vector(), [1, 2, 2, 2, 2, 2, 2, 3, 4, 5]);
+    assert!(tree.validate().is_ok());
 
-    let len = tree.len(); // Sometimes when i set a breakpoint on the assert_eq bellow i end up breakin on a panic.
-    assert_eq!(len, 11);
-  }*/
+    let uri = |name: &str| match tree.find_node_by_name(name).and_then(|n| n.value(&tree)) {
+      Some(XNode::Tag { namespace, .. }) => tree.namespace_uri(*namespace),
+      _ => panic!("no element {name}"),
+    };
+    assert_eq!(uri("doc"), None);
+    assert_eq!(uri("e5"), Some("http://example.org"));
+    assert_eq!(uri("e6"), None);
+    assert_eq!(uri("e7"), Some("http://www.ietf.org"));
+    assert_eq!(uri("e8"), None);
+    assert_eq!(uri("e9"), None);
+
+    let e5 = tree.find_node_by_name("e5").unwrap();
+    let attribute_uri = |key: &str| match e5.value(&tree) {
+      Some(XNode::Tag { attributes: Some(attributes), .. }) => tree.namespace_uri(attributes[key].namespace),
+      _ => panic!("e5 has no attributes"),
+    };
+    assert_eq!(attribute_uri("a:attr"), Some("http://www.w3.org"));
+    assert_eq!(attribute_uri("b:attr"), Some("http://www.ietf.org"));
+    assert_eq!(attribute_uri("attr"), None);
+
+    // `a` is bound to two different uris in different scopes.
+    let w3 = tree.find_namespace_binding("a", "http://www.w3.org");
+    let ietf = tree.find_namespace_binding("a", "http://www.ietf.org");
+    assert!(w3.is_some() && ietf.is_some() && w3 != ietf);
+  }
 }
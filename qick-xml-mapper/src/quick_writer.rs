@@ -1,11 +1,397 @@
-use std::io::BufRead;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::Write;
 
+use quick_xml::escape::escape;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesPI, BytesStart, BytesText, Event};
+use quick_xml::name::QName;
 use quick_xml::Writer;
-use xml_tree::FlatTree;
+use xml_tree::{FlatTree, Node, XNode};
 
+/// How the round-trip writer emits characters outside of ASCII.
+///
+/// Canonical output is always UTF-8, this only applies to `write_with`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonAsciiPolicy {
+  /// Write them as they are, UTF-8 encoded.
+  #[default]
+  Utf8,
+  /// Write them as character references like `&#xE9;` in text and attribute
+  /// values, for ASCII-only consumers. Names, comments and PIs have no
+  /// references and stay UTF-8.
+  NumericRefs,
+}
 
+/// Options for `write_with`.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+  pub non_ascii: NonAsciiPolicy,
+  /// Put each node of element-only content on its own line, indented by
+  /// this once per level, see `write_pretty`.
+  pub indent: Option<Box<str>>,
+}
 
+/// Write a `FlatTree` as XML into a `quick_xml::Writer`.
+///
+/// This is the round-trip writer, it keeps the shape of the source
+/// (self-closing tags, the XML declaration and so on) and does not
+/// canonicalize anything.
+pub fn write<W: Write>(writer: &mut Writer<W>, tree: &FlatTree) -> std::io::Result<()> {
+  write_with(writer, tree, &WriteOptions::default())
+}
 
-pub fn write<W: BufRead>(writer: Writer<W>, tree: FlatTree){
-  
-}
\ No newline at end of file
+/// Like `write`, with control over how the output is escaped.
+pub fn write_with<W: Write>(writer: &mut Writer<W>, tree: &FlatTree, options: &WriteOptions) -> std::io::Result<()> {
+  for event in Events::new(tree, options.clone()) {
+    writer.write_event(event)?;
+  }
+  Ok(())
+}
+
+/// Like `write`, indented by two spaces for reading.
+///
+/// Only element-only content is indented, its whitespace text is replaced by
+/// the indentation. An element with any other text among its children, or
+/// with `xml:space="preserve"`, is written inline as it is along with
+/// everything inside it, so no text changes.
+pub fn write_pretty<W: Write>(writer: &mut Writer<W>, tree: &FlatTree) -> std::io::Result<()> {
+  write_with(writer, tree, &WriteOptions { indent: Some("  ".into()), ..WriteOptions::default() })
+}
+
+/// The quick_xml events `write` would write for `tree`, in document order.
+///
+/// The declaration comes first if the tree has one, and every element that
+/// is not self-closing gets its `End`. Fed to a `Writer` and read back this
+/// gives the tree again. It can not be `FlatTree::to_events` since the tree
+/// crate does not depend on quick_xml.
+pub fn to_events(tree: &FlatTree) -> impl Iterator<Item = Event<'static>> + '_ {
+  Events::new(tree, WriteOptions::default())
+}
+
+/// Iterator behind `to_events` and `write_with`.
+struct Events<'a> {
+  tree: &'a FlatTree,
+  options: WriteOptions,
+  /// Next node to turn into an event.
+  index: usize,
+  /// Elements waiting for their end tag.
+  open: Vec<Open>,
+  /// End tags due before the event for `index`, or the declaration.
+  queued: VecDeque<Event<'static>>,
+  /// Whether anything has been queued yet, the first line is not indented.
+  started: bool,
+}
+
+/// An element that got a `Start` event.
+struct Open {
+  depth: u8,
+  qname: String,
+  /// Whether its content is written without indentation.
+  inline: bool,
+}
+
+impl<'a> Events<'a> {
+  fn new(tree: &'a FlatTree, options: WriteOptions) -> Self {
+    let mut queued = VecDeque::new();
+    if let Some(decl) = tree.xml_decl() {
+      let decl = BytesDecl::new(&decl.version, decl.encoding.as_deref(), decl.standalone.as_deref());
+      queued.push_back(Event::Decl(decl.into_owned()));
+    }
+    let started = !queued.is_empty();
+    Self { tree, options, index: 0, open: Vec::new(), queued, started }
+  }
+
+  /// Queue end tags for every open element at or below `depth`.
+  fn close_open(&mut self, depth: u8) {
+    while let Some(open) = self.open.last() && open.depth >= depth {
+      let open = self.open.pop().unwrap();
+      if !open.inline {
+        self.queue_indent(open.depth);
+      }
+      self.queued.push_back(Event::End(BytesEnd::new(open.qname)));
+    }
+  }
+
+  /// Whether content at the current position is written without
+  /// indentation, always without `WriteOptions::indent`.
+  fn inline(&self) -> bool {
+    self.options.indent.is_none() || self.open.last().is_some_and(|open| open.inline)
+  }
+
+  /// Queue a newline and the indentation for `depth`, unless nothing has
+  /// been written yet. Top level nodes are not indented.
+  fn queue_indent(&mut self, depth: u8) {
+    if let Some(indent) = &self.options.indent && self.started {
+      let text = format!("\n{}", indent.repeat(depth.saturating_sub(1) as usize));
+      self.queued.push_back(Event::Text(BytesText::from_escaped(text)));
+    }
+  }
+
+  fn node_event(&mut self, node: Node) -> Option<Event<'static>> {
+    let tree = self.tree;
+    let event = match node.value(tree)? {
+      XNode::Tag { namespace, name, attributes, self_closing } => {
+        let qname = qualified_name(tree, *namespace, name);
+        let mut start = BytesStart::new(qname.clone());
+        if let Some(attributes) = attributes {
+          for (key, attr) in attributes {
+            start.push_attribute(Attribute {
+              key: QName(key.as_bytes()),
+              value: Cow::Owned(non_ascii(escape_attribute(&attr.value), &self.options).into_bytes()),
+            });
+          }
+        }
+
+        let has_content = node.subtree_end(tree) > self.index + 1;
+        if *self_closing && !has_content {
+          Event::Empty(start)
+        } else {
+          // Empty content has nothing to indent, mixed content and content
+          // under `xml:space="preserve"` must not get any whitespace added.
+          let inline = self.inline()
+            || !has_content
+            || node.attributes_as(tree, &["xml:space"]) == [Some("preserve")]
+            || node.children(tree).iter().any(|child| matches!(child.value(tree), Some(XNode::Text(text)) if !is_whitespace(text)));
+          self.open.push(Open { depth: node.depth(tree), qname, inline });
+          Event::Start(start)
+        }
+      }
+      XNode::Text(text) | XNode::Whitespace(text) => {
+        let escaped = if tree.raw_text() { text.to_string() } else { escape(&**text).into_owned() };
+        Event::Text(BytesText::from_escaped(non_ascii(escaped, &self.options)))
+      }
+      XNode::Comment(text) => Event::Comment(BytesText::from_escaped(text.to_string())),
+      XNode::ProcessingInstruction { target, data } => {
+        // Data read with `keep_pi_whitespace` brings its own separator.
+        let content = match data {
+          Some(data) if data.is_empty() || data.starts_with(char::is_whitespace) => format!("{target}{data}"),
+          Some(data) => format!("{target} {data}"),
+          None => target.to_string(),
+        };
+        Event::PI(BytesPI::new(content))
+      }
+    };
+    Some(event)
+  }
+}
+
+impl Iterator for Events<'_> {
+  type Item = Event<'static>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(event) = self.queued.pop_front() {
+        return Some(event);
+      }
+      let Some(node) = self.tree.node(self.index) else {
+        self.close_open(0);
+        return self.queued.pop_front();
+      };
+
+      let depth = node.depth(self.tree);
+      self.close_open(depth);
+      let inline = self.inline();
+      // Whitespace between indented nodes is replaced by the indentation.
+      if !inline && node.value(self.tree).and_then(XNode::as_text).is_some_and(is_whitespace) {
+        self.index += 1;
+        continue;
+      }
+      let event = self.node_event(node);
+      self.index += 1;
+      if let Some(event) = event {
+        if !inline {
+          self.queue_indent(depth);
+        }
+        self.queued.push_back(event);
+        self.started = true;
+      }
+    }
+  }
+}
+
+/// Escape an attribute value for a double quoted attribute, the same way
+/// canonical output does.
+///
+/// Unlike text `>` is left alone, but tab, newline and carriage return are
+/// written as character references so a parser does not normalize them
+/// into spaces.
+fn escape_attribute(value: &str) -> String {
+  let mut escaped = Vec::with_capacity(value.len());
+  xml_tree::canonical::escape_attribute(&mut escaped, value).expect("writing to a Vec does not fail");
+  String::from_utf8(escaped).expect("escaping keeps utf-8")
+}
+
+/// Apply the `NonAsciiPolicy` to already escaped text.
+fn non_ascii(escaped: String, options: &WriteOptions) -> String {
+  if options.non_ascii == NonAsciiPolicy::Utf8 || escaped.is_ascii() {
+    return escaped;
+  }
+
+  let mut ascii = String::with_capacity(escaped.len());
+  for c in escaped.chars() {
+    if c.is_ascii() {
+      ascii.push(c);
+    } else {
+      ascii.push_str(&format!("&#x{:X};", c as u32));
+    }
+  }
+  ascii
+}
+
+/// Only XML whitespace, a no-break space is text.
+fn is_whitespace(text: &str) -> bool {
+  text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+}
+
+/// `prefix:name`, or just `name` for the default or no namespace.
+fn qualified_name(tree: &FlatTree, namespace: Option<u16>, name: &str) -> String {
+  match tree.get_namespace(namespace) {
+    Some((prefix, _)) if !prefix.is_empty() => format!("{prefix}:{name}"),
+    _ => name.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::quick_reader::read;
+  use quick_xml::Reader;
+
+  fn round_trip(xml: &str) -> String {
+    let mut buf = Vec::new();
+    let tree = read(Reader::from_str(xml), &mut buf).unwrap();
+
+    let mut writer = Writer::new(Vec::new());
+    write(&mut writer, &tree).unwrap();
+    String::from_utf8(writer.into_inner()).unwrap()
+  }
+
+  #[test]
+  fn write_self_closing() {
+    assert_eq!(round_trip("<a/>"), "<a/>");
+    assert_eq!(round_trip("<a></a>"), "<a></a>");
+    assert_eq!(round_trip("<root><a/><b></b></root>"), "<root><a/><b></b></root>");
+  }
+
+  #[test]
+  fn write_mixed_content() {
+    let xml = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body>text<!-- comment --><?pi data?></soap:Body></soap:Envelope>"#;
+    assert_eq!(round_trip(xml), xml);
+  }
+
+  #[test]
+  fn write_xml_declaration() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><root/>"#;
+    assert_eq!(round_trip(xml), xml);
+    assert_eq!(round_trip(r#"<?xml version="1.1"?><root/>"#), r#"<?xml version="1.1"?><root/>"#);
+    assert_eq!(round_trip("<root/>"), "<root/>");
+
+    let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), "<root></root>");
+  }
+
+  #[test]
+  fn write_non_ascii() {
+    let tree = read(Reader::from_str(r#"<a v="é">café<!--é--></a>"#), &mut Vec::new()).unwrap();
+
+    let mut writer = Writer::new(Vec::new());
+    write(&mut writer, &tree).unwrap();
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), r#"<a v="é">café<!--é--></a>"#);
+
+    let options = WriteOptions { non_ascii: NonAsciiPolicy::NumericRefs, ..WriteOptions::default() };
+    let mut writer = Writer::new(Vec::new());
+    write_with(&mut writer, &tree, &options).unwrap();
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), r#"<a v="&#xE9;">caf&#xE9;<!--é--></a>"#);
+
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), "<a v=\"é\">café</a>");
+  }
+
+  #[test]
+  fn write_raw_text() {
+    let xml = "<a>&#38; &amp; &#x3C;&gt;</a>";
+    let options = crate::quick_reader::ReadOptions { raw_text: true, ..Default::default() };
+    let tree = crate::quick_reader::read_with(Reader::from_str(xml), &mut Vec::new(), &options).unwrap();
+    assert!(tree.raw_text());
+
+    let mut writer = Writer::new(Vec::new());
+    write(&mut writer, &tree).unwrap();
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), xml);
+    assert_eq!(round_trip(xml), "<a>&amp; &amp; &lt;&gt;</a>");
+
+    let canonical = "<a>&amp; &amp; &lt;&gt;</a>";
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), canonical);
+    let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+    assert_eq!(xml_tree::canonicalize(&tree, &tree.document()), canonical);
+  }
+
+  #[test]
+  fn events_read_back() {
+    let xml = r#"<?xml version="1.0"?><!--c--><p:root xmlns:p="urn:p" a="1 &amp; 2"><e/><f></f>text &lt;<?pi data?><g><h/></g></p:root>"#;
+    let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+
+    let events: Vec<Event> = to_events(&tree).collect();
+    assert!(matches!(events[0], Event::Decl(_)));
+    assert!(matches!(events.last(), Some(Event::End(e)) if e.name().as_ref() == b"p:root"));
+
+    let mut writer = Writer::new(Vec::new());
+    for event in events {
+      writer.write_event(event).unwrap();
+    }
+    let written = writer.into_inner();
+    let back = read(Reader::from_reader(&written[..]), &mut Vec::new()).unwrap();
+
+    assert_eq!(back.len(), tree.len());
+    for (a, b) in (0..tree.len()).map(|i| (tree.node(i).unwrap(), back.node(i).unwrap())) {
+      assert_eq!(format!("{:?}", a.value(&tree)), format!("{:?}", b.value(&back)));
+      assert_eq!(a.depth(&tree), b.depth(&back));
+    }
+    assert!(back.namespaces().eq(tree.namespaces()));
+    assert_eq!(back.xml_decl(), tree.xml_decl());
+  }
+
+  #[test]
+  fn write_pretty_mixed_content() {
+    let xml = "<?xml version=\"1.0\"?><!--c--><doc>\n <p>Some <b>bold</b> text</p><list><item/>  <item></item></list><p>  <i>x</i></p></doc>";
+    let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+
+    let mut writer = Writer::new(Vec::new());
+    write_pretty(&mut writer, &tree).unwrap();
+    let expected = "<?xml version=\"1.0\"?>
+<!--c-->
+<doc>
+  <p>Some <b>bold</b> text</p>
+  <list>
+    <item/>
+    <item></item>
+  </list>
+  <p>
+    <i>x</i>
+  </p>
+</doc>";
+    assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+  }
+
+  #[test]
+  fn write_pretty_keeps_text() {
+    let pretty = |xml: &str| {
+      let tree = read(Reader::from_str(xml), &mut Vec::new()).unwrap();
+      let mut writer = Writer::new(Vec::new());
+      write_pretty(&mut writer, &tree).unwrap();
+      String::from_utf8(writer.into_inner()).unwrap()
+    };
+
+    // A no-break space is not XML whitespace but text.
+    let xml = "<a><b/>\u{A0}<b/></a>";
+    assert_eq!(pretty(xml), xml);
+
+    let xml = "<a><p xml:space=\"preserve\"> <b/> <c><d/></c></p><e/></a>";
+    assert_eq!(pretty(xml), "<a>\n  <p xml:space=\"preserve\"> <b/> <c><d/></c></p>\n  <e/>\n</a>");
+  }
+
+  #[test]
+  fn write_attribute_escaping() {
+    let xml = r#"<a v="&quot;&lt;&amp;>&#x9;'"/>"#;
+    assert_eq!(round_trip(xml), xml);
+  }
+}
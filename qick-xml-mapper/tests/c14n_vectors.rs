@@ -0,0 +1,255 @@
+//! The examples from section 3 of the Canonical XML 1.0 recommendation,
+//! <https://www.w3.org/TR/xml-c14n>, and from section 2.2 of Exclusive XML
+//! Canonicalization, <https://www.w3.org/TR/xml-exc-c14n>, read with
+//! `quick_reader` and compared byte for byte with the canonical form given
+//! there.
+//!
+//! The document subset examples (3.7 and 3.8) select single attribute and
+//! namespace nodes, which the `canonicalize_subset` mask can not express, and
+//! are left out. The reader does not process DTDs, what a DTD would add comes
+//! in through `ReadOptions` hooks or is set on the tree after reading.
+
+use qick_xml_mapper::quick_reader::{read, read_with, AttributeType, ReadOptions};
+use quick_xml::Reader;
+use xml_tree::{canonicalize_with, CanonicalOptions, FlatTree};
+
+fn canonical(tree: &FlatTree, with_comments: bool) -> String {
+  canonicalize_with(tree, &tree.document(), &CanonicalOptions { with_comments, ..CanonicalOptions::default() })
+}
+
+fn parse(xml: &str) -> FlatTree {
+  read(Reader::from_str(xml), &mut Vec::new()).unwrap()
+}
+
+#[test]
+fn pis_comments_and_outside_of_document_element() {
+  let input = r#"<?xml version="1.0"?>
+
+<?xml-stylesheet   href="doc.xsl"
+   type="text/xsl"   ?>
+
+<!DOCTYPE doc SYSTEM "doc.dtd">
+
+<doc>Hello, world!<!-- Comment 1 --></doc>
+
+<?pi-without-data     ?>
+
+<!-- Comment 2 -->
+
+<!-- Comment 3 -->"#;
+
+  let without_comments = r#"<?xml-stylesheet href="doc.xsl"
+   type="text/xsl"   ?>
+<doc>Hello, world!</doc>
+<?pi-without-data?>"#;
+
+  let with_comments = r#"<?xml-stylesheet href="doc.xsl"
+   type="text/xsl"   ?>
+<doc>Hello, world!<!-- Comment 1 --></doc>
+<?pi-without-data?>
+<!-- Comment 2 -->
+<!-- Comment 3 -->"#;
+
+  let tree = parse(input);
+  assert_eq!(canonical(&tree, false), without_comments);
+  assert_eq!(canonical(&tree, true), with_comments);
+}
+
+#[test]
+fn whitespace_in_document_content() {
+  let input = r#"<doc>
+   <clean>   </clean>
+   <dirty>   A   B   </dirty>
+   <mixed>
+      A
+      <clean>   </clean>
+      B
+      <dirty>   A   B   </dirty>
+      C
+   </mixed>
+</doc>"#;
+
+  assert_eq!(canonical(&parse(input), false), input);
+}
+
+#[test]
+fn start_and_end_tags() {
+  let input = r#"<!DOCTYPE doc [<!ATTLIST e9 attr CDATA "default">]>
+<doc>
+   <e1   />
+   <e2   ></e2>
+   <e3   name = "elem3"   id="elem3"   />
+   <e4   name="elem4"   id="elem4"   ></e4>
+   <e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+      xmlns:b="http://www.ietf.org"
+      xmlns:a="http://www.w3.org"
+      xmlns="http://example.org"/>
+   <e6 xmlns="" xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="" xmlns:a="http://www.w3.org">
+            <e9 xmlns="" xmlns:a="http://www.ietf.org"/>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+
+  let expected = r#"<doc>
+   <e1></e1>
+   <e2></e2>
+   <e3 id="elem3" name="elem3"></e3>
+   <e4 id="elem4" name="elem4"></e4>
+   <e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>
+   <e6 xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="">
+            <e9 xmlns:a="http://www.ietf.org" attr="default"></e9>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+
+  // The default value the ATTLIST declares.
+  let mut tree = parse(input);
+  let e9 = tree.find_node_by_name("e9").unwrap();
+  tree.set_attribute(&e9, "attr", "default");
+
+  assert_eq!(canonical(&tree, false), expected);
+}
+
+#[test]
+fn character_modifications_and_character_references() {
+  let input = r#"<!DOCTYPE doc [
+<!ATTLIST normId id ID #IMPLIED>
+<!ATTLIST normNames attr NMTOKENS #IMPLIED>
+]>
+<doc>
+   <text>First line&#x0d;&#10;Second line</text>
+   <value>&#x32;</value>
+   <compute><![CDATA[value>"0" && value<"10" ?"valid":"error"]]></compute>
+   <compute expr='value>"0" &amp;&amp; value&lt;"10" ?"valid":"error"'>valid</compute>
+   <norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>
+   <normNames attr='   A   &#x20;&#13;&#xa;&#9;   B   '/>
+   <normId id=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>
+</doc>"#;
+
+  let expected = "<doc>
+   <text>First line&#xD;
+Second line</text>
+   <value>2</value>
+   <compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"</compute>
+   <compute expr=\"value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ?&quot;valid&quot;:&quot;error&quot;\">valid</compute>
+   <norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm>
+   <normNames attr=\"A &#xD;&#xA;&#x9; B\"></normNames>
+   <normId id=\"' &#xD;&#xA;&#x9; '\"></normId>
+</doc>";
+
+  // The reader does not process the DTD, the attribute types it declares
+  // come in through the hook instead.
+  fn dtd(element: &str, attribute: &str) -> AttributeType {
+    match (element, attribute) {
+      ("normId", "id") | ("normNames", "attr") => AttributeType::Tokenized,
+      _ => AttributeType::Cdata,
+    }
+  }
+  let options = ReadOptions { attribute_type: Some(dtd), ..ReadOptions::default() };
+  let tree = read_with(Reader::from_str(input), &mut Vec::new(), &options).unwrap();
+
+  assert_eq!(canonical(&tree, false), expected);
+}
+
+#[test]
+fn entity_references() {
+  let input = r#"<!DOCTYPE doc [
+<!ATTLIST doc attrExtEnt ENTITY #IMPLIED>
+<!ENTITY ent1 "Hello">
+<!ENTITY ent2 SYSTEM "world.txt">
+<!ENTITY entExt SYSTEM "earth.gif" NDATA gif>
+<!NOTATION gif SYSTEM "viewgif.exe">
+]>
+<doc attrExtEnt="entExt">
+   &ent1;, &ent2;!
+</doc>
+
+<!-- Let world.txt contain "world" (excluding the quotes) -->"#;
+
+  let expected = r#"<doc attrExtEnt="entExt">
+   Hello, world!
+</doc>"#;
+
+  // The internal entity, and the external one with the content of world.txt.
+  fn entity(name: &str) -> Option<&'static str> {
+    match name {
+      "ent1" => Some("Hello"),
+      "ent2" => Some("world"),
+      _ => None,
+    }
+  }
+  let options = ReadOptions { resolve_entity: Some(entity), ..ReadOptions::default() };
+  let tree = read_with(Reader::from_str(input), &mut Vec::new(), &options).unwrap();
+
+  assert_eq!(canonical(&tree, false), expected);
+}
+
+#[test]
+fn utf8_encoding() {
+  let input = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>
+<doc>&#169;</doc>";
+  let tree = read(Reader::from_reader(&input[..]), &mut Vec::new()).unwrap();
+  assert_eq!(canonical(&tree, false).as_bytes(), b"<doc>\xC2\xA9</doc>");
+
+  // The same character as a Latin-1 byte rather than a reference.
+  let input = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>
+<doc>\xA9</doc>";
+  let tree = read(Reader::from_reader(&input[..]), &mut Vec::new()).unwrap();
+  assert_eq!(canonical(&tree, false).as_bytes(), b"<doc>\xC2\xA9</doc>");
+}
+
+/// The `n1:elem2` subtree of the two documents in section 2.2 of Exclusive
+/// XML Canonicalization.
+fn elem2_subtree(tree: &FlatTree, options: &CanonicalOptions) -> String {
+  canonicalize_with(tree, &tree.find_node_by_name("elem2").unwrap(), options)
+}
+
+const EXCLUSIVE_DOC1: &str = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">
+   <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"/>
+   </n1:elem2>
+</n0:local>"#;
+
+const EXCLUSIVE_DOC2: &str = r#"<n2:pdu xmlns:n1="http://example.com" xmlns:n2="http://foo.example" xml:lang="fr" xml:space="retain">
+   <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"/>
+   </n1:elem2>
+</n2:pdu>"#;
+
+#[test]
+fn inclusive_subtrees_depend_on_context() {
+  let expected1 = r#"<n1:elem2 xmlns:n0="foo:bar" xmlns:n1="http://example.net" xmlns:n3="ftp://example.org" xml:lang="en">
+      <n3:stuff></n3:stuff>
+   </n1:elem2>"#;
+  let expected2 = r#"<n1:elem2 xmlns:n1="http://example.net" xmlns:n2="http://foo.example" xml:lang="en" xml:space="retain">
+      <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+   </n1:elem2>"#;
+
+  let options = CanonicalOptions::default();
+  assert_eq!(elem2_subtree(&parse(EXCLUSIVE_DOC1), &options), expected1);
+  assert_eq!(elem2_subtree(&parse(EXCLUSIVE_DOC2), &options), expected2);
+}
+
+#[test]
+fn exclusive_subtrees_do_not() {
+  let expected = r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+   </n1:elem2>"#;
+
+  let options = CanonicalOptions { exclusive: true, ..CanonicalOptions::default() };
+  assert_eq!(elem2_subtree(&parse(EXCLUSIVE_DOC1), &options), expected);
+  assert_eq!(elem2_subtree(&parse(EXCLUSIVE_DOC2), &options), expected);
+
+  // A prefix on the InclusiveNamespaces PrefixList is declared anyway.
+  let expected = r#"<n1:elem2 xmlns:n0="foo:bar" xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+   </n1:elem2>"#;
+  let options = CanonicalOptions { exclusive: true, inclusive_prefixes: vec!["n0".into()], ..CanonicalOptions::default() };
+  assert_eq!(elem2_subtree(&parse(EXCLUSIVE_DOC1), &options), expected);
+}
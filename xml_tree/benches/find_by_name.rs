@@ -0,0 +1,49 @@
+//! Scanning lookups against `NameIndex` on a wide document.
+//!
+//! No bench framework, just `Instant`: `cargo bench -p xml_tree`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use xml_tree::{FlatTree, TreeBuilder};
+
+const RECORDS: usize = 10_000;
+const QUERIES: usize = 1_000;
+
+/// `<root><record><id/><value/></record>...<last/></root>`
+fn document() -> FlatTree {
+    let mut builder = TreeBuilder::new();
+    builder.start_element(None, "root", None);
+    for _ in 0..RECORDS {
+        builder.start_element(None, "record", None);
+        builder.empty_element(None, "id", None);
+        builder.empty_element(None, "value", None);
+        builder.end_element();
+    }
+    builder.empty_element(None, "last", None);
+    builder.finish()
+}
+
+fn time(label: &str, mut f: impl FnMut() -> usize) {
+    let start = Instant::now();
+    let mut found = 0;
+    for _ in 0..QUERIES {
+        found += black_box(f());
+    }
+    let elapsed = start.elapsed();
+    println!("{label:<24} {:>10.2?} per query ({found} matches)", elapsed / QUERIES as u32);
+}
+
+fn main() {
+    let tree = document();
+
+    time("find_node_by_name", || tree.find_node_by_name(black_box("last")).map_or(0, |_| 1));
+    time("find_all_by_name", || tree.find_all_by_name(black_box("value")).len());
+
+    let start = Instant::now();
+    let index = tree.name_index();
+    println!("{:<24} {:>10.2?}", "name_index build", start.elapsed());
+
+    time("NameIndex::find_first", || index.find_first(black_box("last")).map_or(0, |_| 1));
+    time("NameIndex::find_all", || index.find_all(black_box("value")).map(black_box).count());
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use crate::tree::{FlatTree, Node, XAttribute, XNode};
+
+/// Push based construction of a `FlatTree` from start/end/text events.
+///
+/// Depth is tracked with a stack of open elements, the same way
+/// `quick_reader::read` does it, so any event source can build a tree
+/// without going through quick_xml.
+pub struct TreeBuilder {
+    tree: FlatTree,
+    stack: Vec<Node>,
+    current: Node,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        let tree = FlatTree::new();
+        let current = tree.as_node();
+        Self {
+            tree,
+            stack: Vec::new(),
+            current,
+        }
+    }
+
+    /// Register a namespace in the tree being built, see `FlatTree::add_namespace`.
+    pub fn add_namespace(&mut self, prefix: Box<str>, uri: Box<str>) -> Option<u16> {
+        self.tree.add_namespace(prefix, uri)
+    }
+
+    /// Open an element, following nodes are pushed inside it until `end_element`.
+    pub fn start_element(
+        &mut self,
+        namespace: Option<u16>,
+        name: &str,
+        attributes: Option<BTreeMap<Box<str>, XAttribute>>,
+    ) -> Node {
+        let node = self.current.push(&mut self.tree, tag(namespace, name, attributes, false));
+        self.stack.push(std::mem::replace(&mut self.current, node.clone()));
+        node
+    }
+
+    /// Push a self-closing element, `<name/>`.
+    pub fn empty_element(
+        &mut self,
+        namespace: Option<u16>,
+        name: &str,
+        attributes: Option<BTreeMap<Box<str>, XAttribute>>,
+    ) -> Node {
+        self.current.push(&mut self.tree, tag(namespace, name, attributes, true))
+    }
+
+    /// Close the current element and return it, `None` if nothing is open.
+    pub fn end_element(&mut self) -> Option<Node> {
+        let parent = self.stack.pop()?;
+        Some(std::mem::replace(&mut self.current, parent))
+    }
+
+    pub fn text(&mut self, text: &str) -> Node {
+        self.current.push(&mut self.tree, XNode::Text(text.into()))
+    }
+
+    pub fn comment(&mut self, text: &str) -> Node {
+        self.current.push(&mut self.tree, XNode::Comment(text.into()))
+    }
+
+    pub fn processing_instruction(&mut self, target: &str, data: Option<&str>) -> Node {
+        self.current.push(
+            &mut self.tree,
+            XNode::ProcessingInstruction {
+                target: target.into(),
+                data: data.map(Into::into),
+            },
+        )
+    }
+
+    /// Finish building, any elements still open are closed implicitly.
+    pub fn finish(self) -> FlatTree {
+        self.tree
+    }
+}
+
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn tag(
+    namespace: Option<u16>,
+    name: &str,
+    attributes: Option<BTreeMap<Box<str>, XAttribute>>,
+    self_closing: bool,
+) -> XNode {
+    XNode::Tag {
+        namespace,
+        name: name.into(),
+        attributes,
+        self_closing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_sample_tree() {
+        // <root><child attr="val">text</child><!-- comment --></root>
+        let mut builder = TreeBuilder::new();
+
+        let mut attrs: BTreeMap<Box<str>, XAttribute> = BTreeMap::new();
+        attrs.insert(
+            "attr".into(),
+            XAttribute {
+                namespace: None,
+                value: "val".into(),
+            },
+        );
+
+        builder.start_element(None, "root", None);
+        builder.start_element(None, "child", Some(attrs));
+        builder.text("text");
+        assert_eq!(builder.end_element().unwrap().index(), 1);
+        builder.comment(" comment ");
+        assert_eq!(builder.end_element().unwrap().index(), 0);
+        assert!(builder.end_element().is_none());
+
+        let tree = builder.finish();
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+    }
+
+    #[test]
+    fn build_multiple_roots() {
+        let mut builder = TreeBuilder::new();
+        builder.processing_instruction("pi", None);
+        builder.empty_element(None, "root1", None);
+        builder.start_element(None, "root2", None);
+        builder.empty_element(None, "a", None);
+
+        let tree = builder.finish();
+        assert_eq!(tree.depth_vector(), [1, 1, 1, 2]);
+    }
+}
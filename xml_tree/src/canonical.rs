@@ -0,0 +1,813 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::tree::{split_qname, FlatTree, Node, XAttribute, XNode};
+
+// ── Serialization ───────────────────────────────────────────────────
+//
+// Canonical XML 1.0, inclusive or exclusive. Everything goes through
+// `write_canonical`, the string and digest helpers are thin wrappers around it.
+
+#[derive(Debug, Clone, Default)]
+pub struct CanonicalOptions {
+    /// Keep comments, the "with comments" variant of C14N.
+    pub with_comments: bool,
+    /// End the output with a line feed, for tools that want text files to
+    /// end in one. Off by default since C14N output does not.
+    pub trailing_newline: bool,
+    /// Exclusive C14N: an element only declares the namespaces it and its
+    /// attributes use, and the top of a subtree does not get the `xml:*`
+    /// attributes of its ancestors.
+    pub exclusive: bool,
+    /// The InclusiveNamespaces PrefixList of exclusive C14N, prefixes declared
+    /// the inclusive way even when unused. `""` stands for `#default`.
+    pub inclusive_prefixes: Vec<Box<str>>,
+}
+
+/// Canonicalize the subtree at `node` without comments. Passing
+/// `tree.document()` canonicalizes the whole document.
+///
+/// A document has no text outside of its element, so top level text is
+/// dropped for `tree.document()` even when it is more than whitespace, as in
+/// a forest from `read_fragment`. Canonicalize the top level nodes one at a
+/// time to keep it.
+pub fn canonicalize(tree: &FlatTree, node: &Node) -> String {
+    canonicalize_with(tree, node, &CanonicalOptions::default())
+}
+
+pub fn canonicalize_with(tree: &FlatTree, node: &Node, options: &CanonicalOptions) -> String {
+    let mut out = Vec::new();
+    write_canonical(tree, node, options, &mut out).expect("writing to a Vec does not fail");
+    String::from_utf8(out).expect("canonical output is utf-8")
+}
+
+/// Canonicalize into `buf`, clearing it first. Reusing one buffer across
+/// documents saves the allocation `canonicalize` makes every call, the same
+/// way `quick_reader::read` reuses its event buffer.
+pub fn canonicalize_into(tree: &FlatTree, node: &Node, buf: &mut Vec<u8>) {
+    buf.clear();
+    write_canonical(tree, node, &CanonicalOptions::default(), buf).expect("writing to a Vec does not fail");
+}
+
+/// Byte length of the canonical form of `node`, counted without keeping the
+/// output around.
+pub fn canonical_len(tree: &FlatTree, node: &Node) -> usize {
+    struct CountingWriter(usize);
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = CountingWriter(0);
+    write_canonical(tree, node, &CanonicalOptions::default(), &mut counter)
+        .expect("counting does not fail");
+    counter.0
+}
+
+/// SHA-256 of the canonical form of `node`, streamed into the hasher.
+#[cfg(feature = "sha2")]
+pub fn canonical_digest(tree: &FlatTree, node: &Node) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+
+    struct HashWriter(Sha256);
+
+    impl Write for HashWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.update(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut hasher = HashWriter(Sha256::new());
+    write_canonical(tree, node, &CanonicalOptions::default(), &mut hasher)
+        .expect("hashing does not fail");
+    hasher.0.finalize().into()
+}
+
+/// Write the canonical form of the subtree at `node` (or the whole document
+/// for `tree.document()`, without top level text, see `canonicalize`) into
+/// `w`.
+pub fn write_canonical<W: Write>(
+    tree: &FlatTree,
+    node: &Node,
+    options: &CanonicalOptions,
+    w: &mut W,
+) -> io::Result<()> {
+    let document = node.is_sentinel();
+    let range = if document {
+        0..tree.len()
+    } else if node.is_valid(tree) {
+        node.index()..node.subtree_end(tree)
+    } else {
+        return Ok(());
+    };
+
+    // A subtree still sees the namespaces declared above it.
+    let inherited = if document {
+        BTreeMap::new()
+    } else {
+        let mut ancestors = node.ancestors(tree);
+        ancestors.reverse();
+        ancestors
+            .iter()
+            .fold(BTreeMap::new(), |scope, a| declare(tree, a, scope))
+    };
+
+    let mut open: Vec<Open> = Vec::new();
+    // Whether the document element has been written, nodes outside of it are
+    // separated from it by a line feed on the side facing it.
+    let mut after_root = false;
+
+    for index in range.clone() {
+        let Some(current) = tree.node(index) else { break };
+        let depth = current.depth(tree);
+        close_open(w, &mut open, depth)?;
+
+        match current.value(tree) {
+            Some(XNode::Tag { namespace, name, .. }) => {
+                let empty = BTreeMap::new();
+                let (parent_scope, rendered) = match open.last() {
+                    Some(parent) => (&parent.scope, &parent.rendered),
+                    None => (&inherited, &empty),
+                };
+                let scope = declare(tree, &current, parent_scope.clone());
+
+                let mut attributes = plain_attributes(tree, &current);
+                if !document && index == range.start && !options.exclusive {
+                    inherit_xml_attributes(tree, current.ancestors(tree), &mut attributes);
+                }
+
+                let qname = qualified_name(tree, *namespace, name);
+                let visible = visible_namespaces(tree, *namespace, &scope, &attributes, options);
+                start_tag(w, &qname, &visible, rendered, &attributes)?;
+                let rendered = rendered.clone().into_iter().chain(visible).collect();
+                open.push(Open { depth, qname, scope, rendered });
+                after_root |= document && depth == 1;
+            }
+            // Only whitespace can sit outside the document element.
+            Some(XNode::Text(text) | XNode::Whitespace(text)) if depth > 1 || !document => {
+                if tree.raw_text() {
+                    escape_text(w, &unescape_raw(text))?
+                } else {
+                    escape_text(w, text)?
+                }
+            }
+            Some(XNode::Comment(text)) if options.with_comments => {
+                let outside = (document && depth == 1).then_some(after_root);
+                separated(w, outside, |w| write!(w, "<!--{text}-->"))?;
+            }
+            Some(XNode::ProcessingInstruction { target, data }) => {
+                let outside = (document && depth == 1).then_some(after_root);
+                // Whitespace between target and data is not part of the data.
+                separated(w, outside, |w| match data.as_deref().map(str::trim_start) {
+                    Some(data) if !data.is_empty() => write!(w, "<?{target} {data}?>"),
+                    _ => write!(w, "<?{target}?>"),
+                })?;
+            }
+            _ => {}
+        }
+    }
+
+    close_open(w, &mut open, 0)?;
+    if options.trailing_newline {
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Canonicalize a document subset without comments: only the nodes whose
+/// entry in `included` is true, a mask parallel to the tree's nodes.
+///
+/// This is the node-set input of C14N that XML Signature's XPath transforms
+/// produce. Nodes past the end of the mask count as excluded. Top level text
+/// is dropped like for a whole document.
+pub fn canonicalize_subset(tree: &FlatTree, included: &[bool]) -> String {
+    let mut out = Vec::new();
+    write_canonical_subset(tree, included, &CanonicalOptions::default(), &mut out)
+        .expect("writing to a Vec does not fail");
+    String::from_utf8(out).expect("canonical output is utf-8")
+}
+
+/// Write the canonical form of a document subset into `w`, see
+/// `canonicalize_subset`.
+///
+/// An included element brings its attributes and namespaces along. The
+/// children of an excluded element are still written if they are included
+/// themselves. Namespaces are declared where they differ from the nearest
+/// included ancestor, and an element whose parent is excluded gets the
+/// `xml:*` attributes of the excluded ancestors in between.
+pub fn write_canonical_subset<W: Write>(
+    tree: &FlatTree,
+    included: &[bool],
+    options: &CanonicalOptions,
+    w: &mut W,
+) -> io::Result<()> {
+    let included = |node: &Node| included.get(node.index()).copied().unwrap_or(false);
+
+    let mut open: Vec<Open> = Vec::new();
+    let mut after_root = false;
+
+    for index in 0..tree.len() {
+        let Some(current) = tree.node(index) else { break };
+        let depth = current.depth(tree);
+        close_open(w, &mut open, depth)?;
+
+        let outside = (depth == 1).then_some(after_root);
+        after_root |= depth == 1 && matches!(current.value(tree), Some(XNode::Tag { .. }));
+        if !included(&current) {
+            continue;
+        }
+
+        match current.value(tree) {
+            Some(XNode::Tag { namespace, name, .. }) => {
+                let mut ancestors = current.ancestors(tree);
+                let mut attributes = plain_attributes(tree, &current);
+                if !options.exclusive {
+                    inherit_xml_attributes(
+                        tree,
+                        ancestors.iter().cloned().take_while(|a| !included(a)),
+                        &mut attributes,
+                    );
+                }
+
+                ancestors.reverse();
+                let scope = ancestors
+                    .iter()
+                    .chain([&current])
+                    .fold(BTreeMap::new(), |scope, a| declare(tree, a, scope));
+                let empty = BTreeMap::new();
+                let rendered = open.last().map_or(&empty, |parent| &parent.rendered);
+
+                let qname = qualified_name(tree, *namespace, name);
+                let visible = visible_namespaces(tree, *namespace, &scope, &attributes, options);
+                start_tag(w, &qname, &visible, rendered, &attributes)?;
+                let rendered = rendered.clone().into_iter().chain(visible).collect();
+                open.push(Open { depth, qname, scope, rendered });
+            }
+            Some(XNode::Text(text) | XNode::Whitespace(text)) if depth > 1 => {
+                if tree.raw_text() {
+                    escape_text(w, &unescape_raw(text))?
+                } else {
+                    escape_text(w, text)?
+                }
+            }
+            Some(XNode::Comment(text)) if options.with_comments => {
+                separated(w, outside, |w| write!(w, "<!--{text}-->"))?;
+            }
+            Some(XNode::ProcessingInstruction { target, data }) => {
+                separated(w, outside, |w| match data.as_deref().map(str::trim_start) {
+                    Some(data) if !data.is_empty() => write!(w, "<?{target} {data}?>"),
+                    _ => write!(w, "<?{target}?>"),
+                })?;
+            }
+            _ => {}
+        }
+    }
+
+    close_open(w, &mut open, 0)?;
+    if options.trailing_newline {
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Write a start tag. Namespaces in `scope` are declared unless `rendered`,
+/// the namespaces declared by the elements written around it, already has
+/// them.
+fn start_tag<W: Write>(
+    w: &mut W,
+    qname: &str,
+    scope: &BTreeMap<&str, &str>,
+    rendered: &BTreeMap<&str, &str>,
+    attributes: &[(&str, &XAttribute)],
+) -> io::Result<()> {
+    write!(w, "<{qname}")?;
+
+    // Declarations come before the attributes in prefix order, which is the
+    // map's order with the default ("") first.
+    for (prefix, uri) in scope {
+        let rendered_uri = rendered.get(prefix).copied().unwrap_or("");
+        if *uri == rendered_uri {
+            continue;
+        }
+        if prefix.is_empty() {
+            write!(w, " xmlns=\"")?;
+        } else {
+            write!(w, " xmlns:{prefix}=\"")?;
+        }
+        escape_attribute(w, uri)?;
+        w.write_all(b"\"")?;
+    }
+
+    for (key, attr) in attributes {
+        write!(w, " {key}=\"")?;
+        escape_attribute(w, &attr.value)?;
+        w.write_all(b"\"")?;
+    }
+    w.write_all(b">")
+}
+
+/// Write a comment or PI. Outside of the document element (`outside` is
+/// `Some(after_root)`) it is separated from the element by a line feed,
+/// after it in the prolog and before it in the epilog.
+fn separated<W: Write>(
+    w: &mut W,
+    outside: Option<bool>,
+    write: impl FnOnce(&mut W) -> io::Result<()>,
+) -> io::Result<()> {
+    if outside == Some(true) {
+        w.write_all(b"\n")?;
+    }
+    write(w)?;
+    if outside == Some(false) {
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// An element waiting for its end tag, with the namespaces in scope at it
+/// and the ones the output has declared by then.
+struct Open<'a> {
+    depth: u8,
+    qname: String,
+    scope: BTreeMap<&'a str, &'a str>,
+    rendered: BTreeMap<&'a str, &'a str>,
+}
+
+fn close_open<W: Write>(w: &mut W, open: &mut Vec<Open>, depth: u8) -> io::Result<()> {
+    while let Some(last) = open.last()
+        && last.depth >= depth
+    {
+        let last = open.pop().unwrap();
+        write!(w, "</{}>", last.qname)?;
+    }
+    Ok(())
+}
+
+/// Apply the namespace declarations on `node` on top of `scope`.
+fn declare<'a>(
+    tree: &'a FlatTree,
+    node: &Node,
+    mut scope: BTreeMap<&'a str, &'a str>,
+) -> BTreeMap<&'a str, &'a str> {
+    if let Some(XNode::Tag {
+        attributes: Some(attributes),
+        ..
+    }) = node.value(tree)
+    {
+        for (key, attr) in attributes {
+            if let Some(prefix) = declared_prefix(key) {
+                scope.insert(prefix, &attr.value);
+            }
+        }
+    }
+    scope
+}
+
+/// The part of `scope` an element declares. That is all of it for inclusive
+/// C14N, for exclusive C14N the prefixes of the element and its attributes,
+/// where an unprefixed element uses the default namespace, and
+/// `CanonicalOptions::inclusive_prefixes`.
+fn visible_namespaces<'a>(
+    tree: &'a FlatTree,
+    namespace: Option<u16>,
+    scope: &BTreeMap<&'a str, &'a str>,
+    attributes: &[(&'a str, &'a XAttribute)],
+    options: &CanonicalOptions,
+) -> BTreeMap<&'a str, &'a str> {
+    if !options.exclusive {
+        return scope.clone();
+    }
+
+    let element = tree.get_namespace(namespace).map_or("", |(prefix, _)| prefix);
+    let used = attributes.iter().filter_map(|(key, _)| split_qname(key).0);
+    let listed = options.inclusive_prefixes.iter().map(|prefix| &**prefix);
+    [element]
+        .into_iter()
+        .chain(used)
+        .chain(listed)
+        .filter_map(|prefix| scope.get_key_value(prefix))
+        .map(|(prefix, uri)| (*prefix, *uri))
+        .collect()
+}
+
+/// The attributes of an element in canonical order, without namespace
+/// declarations.
+fn plain_attributes<'a>(tree: &'a FlatTree, node: &Node) -> Vec<(&'a str, &'a XAttribute)> {
+    canonical_attributes(tree, node)
+        .into_iter()
+        .filter(|(key, _)| declared_prefix(key).is_none())
+        .collect()
+}
+
+/// `xml:*` attributes of `ancestors`, nearest first, are carried onto the top
+/// element of a subtree, unless the element sets them itself.
+fn inherit_xml_attributes<'a>(
+    tree: &'a FlatTree,
+    ancestors: impl IntoIterator<Item = Node>,
+    attributes: &mut Vec<(&'a str, &'a XAttribute)>,
+) {
+    for ancestor in ancestors {
+        let Some(XNode::Tag {
+            attributes: Some(inherited),
+            ..
+        }) = ancestor.value(tree)
+        else {
+            continue;
+        };
+
+        for (key, attr) in inherited {
+            if key.starts_with("xml:") && !attributes.iter().any(|(k, _)| *k == &**key) {
+                attributes.push((key, attr));
+            }
+        }
+    }
+    attributes.sort_by(|a, b| canonical_attr_cmp(tree, *a, *b));
+}
+
+/// `prefix:name`, or just `name` for the default or no namespace.
+fn qualified_name(tree: &FlatTree, namespace: Option<u16>, name: &str) -> String {
+    match tree.get_namespace(namespace) {
+        Some((prefix, _)) if !prefix.is_empty() => format!("{prefix}:{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// Write text escaped the C14N way: `&`, `<`, `>` and carriage return become
+/// references. Public for canonicalizers working from something other than a
+/// `FlatTree`, so they escape the same.
+pub fn escape_text<W: Write>(w: &mut W, text: &str) -> io::Result<()> {
+    escape(w, text, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    })
+}
+
+/// Write an attribute value escaped the C14N way for double quotes: `&`,
+/// `<`, `"`, tab, line feed and carriage return become references.
+pub fn escape_attribute<W: Write>(w: &mut W, value: &str) -> io::Result<()> {
+    escape(w, value, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '"' => Some("&quot;"),
+        '\t' => Some("&#x9;"),
+        '\n' => Some("&#xA;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    })
+}
+
+/// Resolve the references in raw text, see `FlatTree::raw_text`. Anything
+/// that is not a well-formed reference is kept as written.
+fn unescape_raw(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let resolved = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                name => {
+                    let code = match name.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => name.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+
+        match resolved {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+fn escape<W: Write>(w: &mut W, text: &str, replace: impl Fn(char) -> Option<&'static str>) -> io::Result<()> {
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        if let Some(replacement) = replace(c) {
+            w.write_all(&text.as_bytes()[last..i])?;
+            w.write_all(replacement.as_bytes())?;
+            last = i + c.len_utf8();
+        }
+    }
+    w.write_all(&text.as_bytes()[last..])
+}
+
+// ── Attribute ordering ──────────────────────────────────────────────
+
+/// Returns the declared prefix if the attribute is a namespace declaration,
+/// `Some("")` for the default namespace.
+fn declared_prefix(key: &str) -> Option<&str> {
+    if key == "xmlns" {
+        return Some("");
+    }
+    key.strip_prefix("xmlns:")
+}
+
+/// Compare two attributes of the same element in C14N order.
+///
+/// Namespace declarations come first sorted by prefix (default namespace
+/// first), then the attributes sorted by namespace uri and local name.
+/// Unqualified attributes have no uri and sort before qualified ones.
+pub fn canonical_attr_cmp(
+    tree: &FlatTree,
+    a: (&str, &XAttribute),
+    b: (&str, &XAttribute),
+) -> Ordering {
+    match (declared_prefix(a.0), declared_prefix(b.0)) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => {
+            let a_uri = tree.namespace_uri(a.1.namespace).unwrap_or("");
+            let b_uri = tree.namespace_uri(b.1.namespace).unwrap_or("");
+            let a_local = split_qname(a.0).1;
+            let b_local = split_qname(b.0).1;
+
+            a_uri.cmp(b_uri).then_with(|| a_local.cmp(b_local))
+        }
+    }
+}
+
+/// The attributes of an element in canonical order, empty for anything that
+/// is not a tag.
+pub fn canonical_attributes<'a>(tree: &'a FlatTree, node: &Node) -> Vec<(&'a str, &'a XAttribute)> {
+    let Some(XNode::Tag {
+        attributes: Some(attributes),
+        ..
+    }) = node.value(tree)
+    else {
+        return Vec::new();
+    };
+
+    let mut ordered: Vec<(&str, &XAttribute)> =
+        attributes.iter().map(|(k, v)| (k.as_ref(), v)).collect();
+    ordered.sort_by(|a, b| canonical_attr_cmp(tree, *a, *b));
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TreeBuilder;
+
+    fn attribute(value: &str) -> XAttribute {
+        XAttribute {
+            namespace: None,
+            value: value.into(),
+        }
+    }
+
+    /// `<doc xmlns="http://example.org" xmlns:a="http://a"><a:e b="2" a="1"/>x &amp; y<!--c--></doc>`
+    fn sample() -> FlatTree {
+        let mut builder = TreeBuilder::new();
+        let default = builder.add_namespace("".into(), "http://example.org".into());
+        let a = builder.add_namespace("a".into(), "http://a".into());
+
+        let mut declarations = BTreeMap::new();
+        declarations.insert("xmlns".into(), attribute("http://example.org"));
+        declarations.insert("xmlns:a".into(), attribute("http://a"));
+        builder.start_element(default, "doc", Some(declarations));
+
+        let mut attributes = BTreeMap::new();
+        attributes.insert("b".into(), attribute("2"));
+        attributes.insert("a".into(), attribute("1"));
+        builder.empty_element(a, "e", Some(attributes));
+        builder.text("x & y");
+        builder.comment("c");
+        builder.end_element();
+
+        builder.finish()
+    }
+
+    #[test]
+    fn trailing_newline() {
+        let mut builder = TreeBuilder::new();
+        builder.empty_element(None, "root", None);
+        let tree = builder.finish();
+
+        assert_eq!(canonicalize(&tree, &tree.document()), "<root></root>");
+        let options = CanonicalOptions { trailing_newline: true, ..CanonicalOptions::default() };
+        assert_eq!(canonicalize_with(&tree, &tree.document(), &options), "<root></root>\n");
+    }
+
+    #[test]
+    fn canonicalize_document() {
+        let tree = sample();
+        assert_eq!(
+            canonicalize(&tree, &tree.document()),
+            r#"<doc xmlns="http://example.org" xmlns:a="http://a"><a:e a="1" b="2"></a:e>x &amp; y</doc>"#
+        );
+
+        let options = CanonicalOptions { with_comments: true, ..CanonicalOptions::default() };
+        assert!(canonicalize_with(&tree, &tree.document(), &options).ends_with("y<!--c--></doc>"));
+    }
+
+    #[test]
+    fn canonicalize_processing_instructions() {
+        let mut builder = TreeBuilder::new();
+        builder.processing_instruction("before", Some("a"));
+        builder.start_element(None, "doc", None);
+        builder.processing_instruction("inside", None);
+        builder.end_element();
+        builder.processing_instruction("after", None);
+        builder.processing_instruction("last", Some("b"));
+        let tree = builder.finish();
+
+        assert_eq!(
+            canonicalize(&tree, &tree.document()),
+            "<?before a?>\n<doc><?inside?></doc>\n<?after?>\n<?last b?>"
+        );
+        // Only the document gets the line feeds.
+        assert_eq!(canonicalize(&tree, &tree.node(1).unwrap()), "<doc><?inside?></doc>");
+    }
+
+    #[test]
+    fn canonicalize_top_level_comments() {
+        let mut builder = TreeBuilder::new();
+        builder.comment("a");
+        builder.empty_element(None, "root", None);
+        builder.comment("b");
+        let tree = builder.finish();
+
+        let options = CanonicalOptions { with_comments: true, ..CanonicalOptions::default() };
+        assert_eq!(
+            canonicalize_with(&tree, &tree.document(), &options),
+            "<!--a-->\n<root></root>\n<!--b-->"
+        );
+        assert_eq!(canonicalize(&tree, &tree.document()), "<root></root>");
+    }
+
+    #[test]
+    fn canonicalize_top_level_text() {
+        // A forest like `read_fragment` gives for `a <b/>c`.
+        let mut builder = TreeBuilder::new();
+        builder.text("a ");
+        builder.empty_element(None, "b", None);
+        builder.text("c");
+        let tree = builder.finish();
+
+        assert_eq!(canonicalize(&tree, &tree.document()), "<b></b>");
+        let nodes: String = tree.top_level_nodes().map(|node| canonicalize(&tree, &node)).collect();
+        assert_eq!(nodes, "a <b></b>c");
+    }
+
+    #[test]
+    fn canonicalize_subtree_keeps_namespace_context() {
+        let tree = sample();
+        let e = tree.node(1).unwrap();
+        assert_eq!(
+            canonicalize(&tree, &e),
+            r#"<a:e xmlns="http://example.org" xmlns:a="http://a" a="1" b="2"></a:e>"#
+        );
+    }
+
+    #[test]
+    fn canonicalize_document_subset() {
+        let tree = sample();
+        let options = CanonicalOptions { with_comments: true, ..CanonicalOptions::default() };
+        let subset = |included: &[bool]| {
+            let mut out = Vec::new();
+            write_canonical_subset(&tree, included, &options, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // <doc> and <a:e> but neither the text nor the comment.
+        assert_eq!(
+            subset(&[true, true, false, false]),
+            r#"<doc xmlns="http://example.org" xmlns:a="http://a"><a:e a="1" b="2"></a:e></doc>"#
+        );
+        assert_eq!(
+            subset(&[true, true, false, true]),
+            r#"<doc xmlns="http://example.org" xmlns:a="http://a"><a:e a="1" b="2"></a:e><!--c--></doc>"#
+        );
+        assert_eq!(subset(&[true; 4]), canonicalize_with(&tree, &tree.document(), &options));
+        assert_eq!(canonicalize_subset(&tree, &[true; 4]), canonicalize(&tree, &tree.document()));
+
+        // Without its parent the element declares what it has in scope.
+        assert_eq!(subset(&[false, true]), canonicalize(&tree, &tree.node(1).unwrap()));
+        assert_eq!(subset(&[false, false, true, true]), "x &amp; y<!--c-->");
+        assert_eq!(subset(&[]), "");
+    }
+
+    #[test]
+    fn canonicalize_subset_context() {
+        // <r xmlns="urn:r" xml:lang="en"><s xmlns="" xml:space="preserve"><t/></s></r>
+        let mut builder = TreeBuilder::new();
+        let r = builder.add_namespace("".into(), "urn:r".into());
+        let mut attributes = BTreeMap::new();
+        attributes.insert("xmlns".into(), attribute("urn:r"));
+        attributes.insert("xml:lang".into(), attribute("en"));
+        builder.start_element(r, "r", Some(attributes));
+        let mut attributes = BTreeMap::new();
+        attributes.insert("xmlns".into(), attribute(""));
+        attributes.insert("xml:space".into(), attribute("preserve"));
+        builder.start_element(None, "s", Some(attributes));
+        builder.empty_element(None, "t", None);
+        builder.end_element();
+        builder.end_element();
+        let tree = builder.finish();
+
+        // The default namespace is undeclared against the nearest included
+        // ancestor, and only the xml:* attributes between the two come along.
+        assert_eq!(
+            canonicalize_subset(&tree, &[true, false, true]),
+            r#"<r xmlns="urn:r" xml:lang="en"><t xmlns="" xml:space="preserve"></t></r>"#
+        );
+        assert_eq!(
+            canonicalize_subset(&tree, &[false, false, true]),
+            r#"<t xml:lang="en" xml:space="preserve"></t>"#
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let mut text = Vec::new();
+        escape_text(&mut text, "a<b>&\"c\"\r").unwrap();
+        assert_eq!(text, b"a&lt;b&gt;&amp;\"c\"&#xD;");
+
+        let mut value = Vec::new();
+        escape_attribute(&mut value, "a<b>&\"\t\n\r").unwrap();
+        assert_eq!(value, b"a&lt;b>&amp;&quot;&#x9;&#xA;&#xD;");
+    }
+
+    #[test]
+    fn canonicalize_attribute_escaping() {
+        let mut tree = sample();
+        let doc = tree.node(0).unwrap();
+        tree.set_attribute(&doc, "v", "\"<&>\t");
+
+        assert!(canonicalize(&tree, &doc).contains(r#" v="&quot;&lt;&amp;>&#x9;">"#));
+    }
+
+    #[test]
+    fn canonicalize_into_reuses_buffer() {
+        let tree = sample();
+        let mut buf = b"left over".to_vec();
+
+        canonicalize_into(&tree, &tree.document(), &mut buf);
+        let first = buf.clone();
+        canonicalize_into(&tree, &tree.document(), &mut buf);
+        assert_eq!(buf, first);
+        assert_eq!(buf, canonicalize(&tree, &tree.document()).as_bytes());
+
+        canonicalize_into(&tree, &tree.node(1).unwrap(), &mut buf);
+        assert_eq!(buf, canonicalize(&tree, &tree.node(1).unwrap()).as_bytes());
+    }
+
+    #[test]
+    fn length_matches_canonical_string() {
+        let tree = sample();
+        for node in [tree.document(), tree.node(1).unwrap()] {
+            assert_eq!(canonical_len(&tree, &node), canonicalize(&tree, &node).len());
+        }
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn digest_matches_canonical_string() {
+        use sha2::{Digest, Sha256};
+
+        let tree = sample();
+        let expected: [u8; 32] = Sha256::digest(canonicalize(&tree, &tree.document()).as_bytes()).into();
+        assert_eq!(canonical_digest(&tree, &tree.document()), expected);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use crate::tree::{FlatTree, Node, XAttribute, XNode};
+
+/// A nested view of a `FlatTree`, each element owning its children.
+///
+/// Easier to pattern match on than the flat arrays, but every node is its
+/// own allocation. Namespaces are carried as `(prefix, uri)` instead of
+/// registry ids so a `DomNode` does not depend on the tree it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomNode {
+    /// The document itself, holding the top level nodes.
+    Document { children: Vec<DomNode> },
+    Element {
+        namespace: Option<DomNamespace>,
+        name: Box<str>,
+        /// Keyed by qualified name like `XNode::Tag`, declarations included.
+        attributes: BTreeMap<Box<str>, DomAttribute>,
+        self_closing: bool,
+        children: Vec<DomNode>,
+    },
+    Text(Box<str>),
+    Comment(Box<str>),
+    ProcessingInstruction {
+        target: Box<str>,
+        data: Option<Box<str>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomNamespace {
+    pub prefix: Box<str>,
+    pub uri: Box<str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomAttribute {
+    pub namespace: Option<DomNamespace>,
+    pub value: Box<str>,
+}
+
+impl DomNode {
+    /// Child nodes, empty for anything but a document or an element.
+    pub fn children(&self) -> &[DomNode] {
+        match self {
+            DomNode::Document { children } | DomNode::Element { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<DomNode>> {
+        match self {
+            DomNode::Document { children } | DomNode::Element { children, .. } => Some(children),
+            _ => None,
+        }
+    }
+}
+
+impl FlatTree {
+    /// Build the nested form of the whole tree in one pass over the depth
+    /// array. The result is always a `DomNode::Document`.
+    pub fn to_dom(&self) -> DomNode {
+        // Nodes whose children are still being collected, with their depth.
+        let mut stack: Vec<(u8, DomNode)> = vec![(0, DomNode::Document { children: Vec::new() })];
+
+        for index in 0..self.len() {
+            let Some(node) = self.node(index) else { break };
+            let depth = node.depth(self);
+            collapse(&mut stack, depth);
+
+            let Some(xnode) = node.value(self) else { continue };
+            stack.push((depth, dom_node(self, xnode)));
+        }
+
+        collapse(&mut stack, 1);
+        stack.pop().map_or(DomNode::Document { children: Vec::new() }, |(_, dom)| dom)
+    }
+
+    /// Flatten a nested tree, registering namespaces as they come up. A
+    /// `DomNode::Document` contributes its children, anything else becomes
+    /// the single top level node.
+    pub fn from_dom(dom: &DomNode) -> FlatTree {
+        let mut tree = FlatTree::new();
+        let document = tree.document();
+        match dom {
+            DomNode::Document { children } => {
+                for child in children {
+                    flatten(&mut tree, &document, child);
+                }
+            }
+            dom => flatten(&mut tree, &document, dom),
+        }
+        tree
+    }
+}
+
+fn flatten(tree: &mut FlatTree, parent: &Node, dom: &DomNode) {
+    let mut register = |namespace: &Option<DomNamespace>| {
+        namespace
+            .as_ref()
+            .and_then(|ns| tree.add_namespace(ns.prefix.clone(), ns.uri.clone()))
+    };
+
+    let xnode = match dom {
+        DomNode::Document { children } => {
+            // A document nested in a tree has no node of its own.
+            for child in children {
+                flatten(tree, parent, child);
+            }
+            return;
+        }
+        DomNode::Element {
+            namespace,
+            name,
+            attributes,
+            self_closing,
+            ..
+        } => {
+            let attributes: BTreeMap<Box<str>, XAttribute> = attributes
+                .iter()
+                .map(|(key, attr)| {
+                    let attr = XAttribute {
+                        namespace: register(&attr.namespace),
+                        value: attr.value.clone(),
+                    };
+                    (key.clone(), attr)
+                })
+                .collect();
+
+            XNode::Tag {
+                namespace: register(namespace),
+                name: name.clone(),
+                attributes: (!attributes.is_empty()).then_some(attributes),
+                self_closing: *self_closing,
+            }
+        }
+        DomNode::Text(text) => XNode::Text(text.clone()),
+        DomNode::Comment(text) => XNode::Comment(text.clone()),
+        DomNode::ProcessingInstruction { target, data } => XNode::ProcessingInstruction {
+            target: target.clone(),
+            data: data.clone(),
+        },
+    };
+
+    let node = parent.push(tree, xnode);
+    for child in dom.children() {
+        flatten(tree, &node, child);
+    }
+}
+
+/// Pop everything at or below `depth` into its parent's children.
+fn collapse(stack: &mut Vec<(u8, DomNode)>, depth: u8) {
+    while stack.len() > 1 && stack.last().is_some_and(|(d, _)| *d >= depth) {
+        let (_, dom) = stack.pop().unwrap();
+        if let Some(children) = stack.last_mut().and_then(|(_, parent)| parent.children_mut()) {
+            children.push(dom);
+        }
+    }
+}
+
+fn dom_node(tree: &FlatTree, xnode: &XNode) -> DomNode {
+    let namespace = |id: Option<u16>| {
+        tree.get_namespace(id).map(|(prefix, uri)| DomNamespace {
+            prefix: prefix.into(),
+            uri: uri.into(),
+        })
+    };
+
+    match xnode {
+        XNode::Tag {
+            namespace: id,
+            name,
+            attributes,
+            self_closing,
+        } => DomNode::Element {
+            namespace: namespace(*id),
+            name: name.clone(),
+            attributes: attributes
+                .iter()
+                .flatten()
+                .map(|(key, attr)| {
+                    let attr = DomAttribute {
+                        namespace: namespace(attr.namespace),
+                        value: attr.value.clone(),
+                    };
+                    (key.clone(), attr)
+                })
+                .collect(),
+            self_closing: *self_closing,
+            children: Vec::new(),
+        },
+        XNode::Text(text) | XNode::Whitespace(text) => DomNode::Text(text.clone()),
+        XNode::Comment(text) => DomNode::Comment(text.clone()),
+        XNode::ProcessingInstruction { target, data } => DomNode::ProcessingInstruction {
+            target: target.clone(),
+            data: data.clone(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TreeBuilder;
+
+    /// `<root><child attr="val">text</child><!-- comment --></root>`
+    fn sample_tree() -> FlatTree {
+        let mut builder = TreeBuilder::new();
+        builder.start_element(None, "root", None);
+        let child = builder.start_element(None, "child", None);
+        builder.text("text");
+        builder.end_element();
+        builder.comment(" comment ");
+        builder.end_element();
+
+        let mut tree = builder.finish();
+        tree.set_attribute(&child, "attr", "val");
+        tree
+    }
+
+    #[test]
+    fn to_dom_nesting() {
+        let dom = sample_tree().to_dom();
+
+        let [root] = dom.children() else {
+            panic!("expected a single root, got {dom:?}");
+        };
+        let [child, comment] = root.children() else {
+            panic!("expected child and comment under root");
+        };
+        assert_eq!(*comment, DomNode::Comment(" comment ".into()));
+        assert_eq!(child.children(), [DomNode::Text("text".into())]);
+
+        let DomNode::Element { name, attributes, .. } = child else {
+            panic!("expected an element");
+        };
+        assert_eq!(&**name, "child");
+        assert_eq!(&*attributes["attr"].value, "val");
+    }
+
+    #[test]
+    fn from_dom_round_trip() {
+        let mut builder = TreeBuilder::new();
+        let a = builder.add_namespace("a".into(), "urn:a".into());
+        builder.processing_instruction("pi", None);
+        let root = builder.start_element(a, "root", None);
+        builder.empty_element(None, "empty", None);
+        builder.text("x");
+        builder.end_element();
+        let mut tree = builder.finish();
+        tree.set_attribute(&root, "xmlns:a", "urn:a");
+        tree.set_attribute(&root, "a:attr", "1");
+
+        let dom = tree.to_dom();
+        let flattened = FlatTree::from_dom(&dom);
+        assert_eq!(flattened.depth_vector(), tree.depth_vector());
+        assert_eq!(flattened.to_dom(), dom);
+        assert_eq!(flattened.namespaces().collect::<Vec<_>>(), [(0, "a", "urn:a")]);
+        assert_eq!(
+            crate::canonicalize(&flattened, &flattened.document()),
+            crate::canonicalize(&tree, &tree.document())
+        );
+
+        let sample = sample_tree();
+        let flattened = FlatTree::from_dom(&sample.to_dom());
+        assert_eq!(flattened.depth_vector(), [1, 2, 3, 2]);
+        assert_eq!(flattened.to_dom(), sample.to_dom());
+    }
+
+    #[test]
+    fn to_dom_forest() {
+        let mut builder = TreeBuilder::new();
+        builder.empty_element(None, "a", None);
+        builder.start_element(None, "b", None);
+        builder.text("x");
+        let dom = builder.finish().to_dom();
+
+        assert_eq!(dom.children().len(), 2);
+        assert_eq!(dom.children()[1].children(), [DomNode::Text("x".into())]);
+        assert_eq!(FlatTree::new().to_dom(), DomNode::Document { children: Vec::new() });
+    }
+}
@@ -1,3 +1,14 @@
+pub mod builder;
+pub mod canonical;
+pub mod dom;
 pub mod tree;
 
-pub use tree::{FlatTree, Node, XAttribute, XNode};
\ No newline at end of file
+pub use builder::TreeBuilder;
+pub use canonical::{
+    canonical_attr_cmp, canonical_attributes, canonical_len, canonicalize, canonicalize_into,
+    canonicalize_subset, canonicalize_with, write_canonical, write_canonical_subset, CanonicalOptions,
+};
+#[cfg(feature = "sha2")]
+pub use canonical::canonical_digest;
+pub use dom::{DomAttribute, DomNamespace, DomNode};
+pub use tree::{split_qname, FlatTree, NameIndex, NavError, Node, NodeCounts, NormalizeOptions, TreeError, XAttribute, XNode, XmlDecl, XML_NAMESPACE};
\ No newline at end of file
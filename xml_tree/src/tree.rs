@@ -1,40 +1,187 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// The uri the `xml` prefix is bound to without a declaration.
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
 
 // ── Node types ──────────────────────────────────────────────────────
+
+/// Attributes are keyed by their qualified name (`attr`, `ns:attr`), namespace
+/// declarations (`xmlns`, `xmlns:ns`) are kept alongside them.
 #[derive(Debug, Clone)]
 pub struct XAttribute {
     pub namespace: Option<u16>,
     pub value: Box<str>,
 }
 
+#[derive(Debug, Clone)]
 pub enum XNode {
     Tag {
         namespace: Option<u16>,
         name: Box<str>,
         attributes: Option<BTreeMap<Box<str>, XAttribute>>,
+        /// Written as `<a/>` in the source rather than `<a></a>`. Only the
+        /// round-trip writer cares, canonical output always uses start/end tags.
+        self_closing: bool,
     },
     Text(Box<str>),
+    /// Text of only XML whitespace, for readers asked to tell it apart from
+    /// other text. Everything else treats it as `Text`.
+    Whitespace(Box<str>),
     Comment(Box<str>),
     ProcessingInstruction {
         target: Box<str>,
+        /// Can start with the whitespace that separated it from the target,
+        /// canonical output and `FlatTree::logical_hash` skip that.
         data: Option<Box<str>>,
     },
 }
 
+impl XNode {
+    /// The text of a `Text` or `Whitespace` node.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            XNode::Text(text) | XNode::Whitespace(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// The `<?xml ...?>` declaration, kept beside the nodes rather than as one.
+/// Only the round-trip writer uses it, canonical output has no declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlDecl {
+    pub version: Box<str>,
+    pub encoding: Option<Box<str>>,
+    pub standalone: Option<Box<str>>,
+}
+
+/// Number of nodes of each kind in a tree, see `FlatTree::node_counts`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeCounts {
+    pub tags: usize,
+    pub text: usize,
+    pub comments: usize,
+    pub pis: usize,
+}
+
+/// What `FlatTree::normalize` does. By default text is coalesced and line
+/// endings normalized, whitespace is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    /// Merge adjacent text siblings, see `FlatTree::coalesce_text`.
+    pub coalesce_text: bool,
+    /// Turn `\r\n` and lone `\r` in text into `\n`, as a parser would.
+    pub line_endings: bool,
+    /// Drop whitespace-only text nodes, except under `xml:space="preserve"`.
+    pub trim_whitespace: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            coalesce_text: true,
+            line_endings: true,
+            trim_whitespace: false,
+        }
+    }
+}
+
+/// A broken invariant found by `FlatTree::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeError {
+    /// `nodes` and `depth` are out of step.
+    LengthMismatch { nodes: usize, depth: usize },
+    /// A node deeper than one below the node before it, or at depth 0.
+    /// The first node may be at depth 1 at most.
+    DepthJump { index: usize, depth: u8, previous: u8 },
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::LengthMismatch { nodes, depth } => {
+                write!(f, "{nodes} nodes but {depth} depth entries")
+            }
+            TreeError::DepthJump { index, depth, previous } => {
+                write!(f, "node {index} is at depth {depth} after a node at depth {previous}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+/// Why a `Node::try_*` navigation found nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavError {
+    /// The node is at the top level, the document has no node of its own.
+    IsRoot,
+    /// The document sentinel, which sits above everything.
+    Sentinel,
+    /// The index is past the end of the tree, it was removed or belongs to
+    /// another tree.
+    Stale,
+}
+
+impl fmt::Display for NavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NavError::IsRoot => write!(f, "node is at the top level"),
+            NavError::Sentinel => write!(f, "node is the document sentinel"),
+            NavError::Stale => write!(f, "node is not in the tree"),
+        }
+    }
+}
+
+impl std::error::Error for NavError {}
+
+/// Split a qualified name at its first colon into prefix and local name.
+/// `b` has no prefix, `:x` has an empty one.
+pub fn split_qname(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix), local),
+        None => (None, name),
+    }
+}
+
+/// True for `xmlns` and `xmlns:prefix` attribute keys.
+pub(crate) fn is_namespace_declaration(key: &str) -> bool {
+    key == "xmlns" || key.starts_with("xmlns:")
+}
+
 // ── Flat tree ───────────────────────────────────────────────────────
 
+/// Nodes in preorder with their depth, top level nodes at depth 1.
+///
+/// There can be any number of top level nodes, so a tree is a forest rather
+/// than strictly a document with one element: fragments, or a document with
+/// comments and PIs around its element. Navigation does not assume a root.
+#[derive(Debug)]
 pub struct FlatTree {
     nodes: Vec<XNode>,
     /// Parallel to `nodes` – depth of each node. Max 255 levels deep.
     depth: Vec<u8>,
 
-    /// Namespace registry: (prefix, uri). Nodes reference by u8 index.
-    // I need to move namespaces up into the nodes :/
-    namespaces: Vec<(Box<str>, Box<str>)>,
-    namespace_map: BTreeMap<Box<str>, usize>,
-}
+    /// Namespace registry as (prefix, uri), nodes refer to an entry by its
+    /// index as a `u16` id. Uris are shared between prefixes bound to the
+    /// same one. `add_namespace` stops at 65536 entries, so every index fits
+    /// an id.
+    namespaces: Vec<(Box<str>, Arc<str>)>,
+    /// Ids per prefix in registration order, a prefix bound to different
+    /// uris in different scopes has one id per binding.
+    namespace_map: BTreeMap<Box<str>, Vec<u16>>,
+
+    xml_decl: Option<XmlDecl>,
 
-// Todo add a flattree iterator so i can go over each node and print them.
+    /// Source byte ranges, a prefix of `nodes`: nodes appended later have no
+    /// span, and edits that move nodes around drop all of them.
+    spans: Vec<(usize, usize)>,
+
+    /// Text nodes hold the source text with its references, see `raw_text`.
+    raw_text: bool,
+}
 
 impl FlatTree {
     pub fn new() -> Self {
@@ -43,6 +190,9 @@ impl FlatTree {
             depth: Vec::new(),
             namespaces: Vec::new(),
             namespace_map: BTreeMap::new(),
+            xml_decl: None,
+            spans: Vec::new(),
+            raw_text: false,
         }
     }
 
@@ -55,6 +205,63 @@ impl FlatTree {
         Node { index: usize::MAX }
     }
 
+    /// An empty tree with room for `capacity` nodes before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+            depth: Vec::with_capacity(capacity),
+            namespaces: Vec::new(),
+            namespace_map: BTreeMap::new(),
+            xml_decl: None,
+            spans: Vec::new(),
+            raw_text: false,
+        }
+    }
+
+    /// Drop excess capacity, for trees that are kept around after parsing.
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+        self.depth.shrink_to_fit();
+        self.namespaces.shrink_to_fit();
+    }
+
+    /// The sentinel node, standing in for the document itself. Depth 1 nodes
+    /// hang under it and canonicalizing it covers the whole tree.
+    pub fn document(&self) -> Node {
+        Node { index: usize::MAX }
+    }
+
+    /// Whether text nodes hold escaped source text, `&amp;` and `&#38;` as
+    /// written, rather than the characters they stand for. The round-trip
+    /// writer then copies text out as is. Canonical output is the same either
+    /// way, references are always resolved for it.
+    pub fn raw_text(&self) -> bool {
+        self.raw_text
+    }
+
+    /// Mark the text nodes as raw or not, this does not convert them.
+    pub fn set_raw_text(&mut self, raw: bool) {
+        self.raw_text = raw;
+    }
+
+    pub fn xml_decl(&self) -> Option<&XmlDecl> {
+        self.xml_decl.as_ref()
+    }
+
+    pub fn set_xml_decl(&mut self, decl: Option<XmlDecl>) {
+        self.xml_decl = decl;
+    }
+
+    /// Attach source byte ranges, one `(start, end)` per node in order.
+    /// Refused, returning false, if there are more spans than nodes.
+    pub fn set_source_spans(&mut self, spans: Vec<(usize, usize)>) -> bool {
+        if spans.len() > self.nodes.len() {
+            return false;
+        }
+        self.spans = spans;
+        true
+    }
+
     pub fn len(&self) -> usize {
         self.nodes.len()
     }
@@ -67,6 +274,207 @@ impl FlatTree {
         self.depth.clone()
     }
 
+    /// Check that `nodes` and `depth` line up and every depth is between 1 and
+    /// one more than the node before, the first node counting the document at
+    /// depth 0 as its predecessor. The parser never breaks this, it is meant
+    /// as a debug assertion after editing a tree by hand.
+    pub fn validate(&self) -> Result<(), TreeError> {
+        if self.nodes.len() != self.depth.len() {
+            return Err(TreeError::LengthMismatch {
+                nodes: self.nodes.len(),
+                depth: self.depth.len(),
+            });
+        }
+
+        let mut previous = 0;
+        for (index, &depth) in self.depth.iter().enumerate() {
+            if depth == 0 || depth > previous + 1 {
+                return Err(TreeError::DepthJump { index, depth, previous });
+            }
+            previous = depth;
+        }
+        Ok(())
+    }
+
+    /// A copy of the tree with `f` applied to every node, in document order.
+    ///
+    /// The depths, namespace registry and declaration are copied as they are,
+    /// so namespace ids `f` hands out mean the same in the new tree. Ids that
+    /// are not registered become no namespace.
+    pub fn map_nodes(&self, f: impl Fn(&XNode) -> XNode) -> FlatTree {
+        let registered = |namespace: Option<u16>| namespace.filter(|id| usize::from(*id) < self.namespaces.len());
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|xnode| {
+                let mut xnode = f(xnode);
+                if let XNode::Tag { namespace, attributes, .. } = &mut xnode {
+                    *namespace = registered(*namespace);
+                    for attr in attributes.iter_mut().flat_map(BTreeMap::values_mut) {
+                        attr.namespace = registered(attr.namespace);
+                    }
+                }
+                xnode
+            })
+            .collect();
+
+        FlatTree {
+            nodes,
+            depth: self.depth.clone(),
+            namespaces: self.namespaces.clone(),
+            namespace_map: self.namespace_map.clone(),
+            xml_decl: self.xml_decl.clone(),
+            spans: self.spans.clone(),
+            raw_text: self.raw_text,
+        }
+    }
+
+    /// Count the nodes of each kind in one pass.
+    pub fn node_counts(&self) -> NodeCounts {
+        let mut counts = NodeCounts::default();
+        for xnode in &self.nodes {
+            match xnode {
+                XNode::Tag { .. } => counts.tags += 1,
+                XNode::Text(_) | XNode::Whitespace(_) => counts.text += 1,
+                XNode::Comment(_) => counts.comments += 1,
+                XNode::ProcessingInstruction { .. } => counts.pis += 1,
+            }
+        }
+        counts
+    }
+
+    /// A hash of the content that survives reformatting: element structure,
+    /// namespace uris and local names, attributes in any order, PIs and text
+    /// with whitespace collapsed. Comments, whitespace-only text, prefixes
+    /// and namespace declarations do not count.
+    ///
+    /// For dedup and caches, not signatures. It uses the std hasher, so the
+    /// value is only stable within one build of the program.
+    pub fn logical_hash(&self) -> u64 {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        for (xnode, depth) in self.nodes.iter().zip(&self.depth) {
+            match xnode {
+                XNode::Tag { namespace, name, attributes, .. } => {
+                    (depth, 0u8, self.namespace_uri(*namespace), name).hash(&mut hasher);
+
+                    let mut attributes: Vec<_> = attributes
+                        .iter()
+                        .flatten()
+                        .filter(|(key, _)| !is_namespace_declaration(key))
+                        .map(|(key, attr)| {
+                            let local = split_qname(key).1;
+                            (self.namespace_uri(attr.namespace), local, &*attr.value)
+                        })
+                        .collect();
+                    attributes.sort_unstable();
+                    attributes.hash(&mut hasher);
+                }
+                XNode::Text(text) | XNode::Whitespace(text) => {
+                    let words: Vec<&str> = text.split_whitespace().collect();
+                    if !words.is_empty() {
+                        (depth, 1u8, words).hash(&mut hasher);
+                    }
+                }
+                XNode::ProcessingInstruction { target, data } => {
+                    let data = data.as_deref().map(str::trim_start).filter(|data| !data.is_empty());
+                    (depth, 2u8, target, data).hash(&mut hasher);
+                }
+                XNode::Comment(_) => {}
+            }
+        }
+        hasher.finish()
+    }
+
+    /// The first top level element, what is usually meant by the root. Unlike
+    /// index 0 this skips PIs and comments in the prolog.
+    pub fn document_element(&self) -> Option<Node> {
+        self.top_level_nodes()
+            .find(|node| matches!(node.value(self), Some(XNode::Tag { .. })))
+    }
+
+    /// Every node in document order.
+    pub fn iter(&self) -> impl Iterator<Item = Node> + '_ {
+        (0..self.len()).map(|index| Node { index })
+    }
+
+    /// Element nodes in document order.
+    pub fn tags(&self) -> impl Iterator<Item = Node> + '_ {
+        self.iter_kind(|xnode| matches!(xnode, XNode::Tag { .. }))
+    }
+
+    pub fn texts(&self) -> impl Iterator<Item = Node> + '_ {
+        self.iter_kind(|xnode| xnode.as_text().is_some())
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = Node> + '_ {
+        self.iter_kind(|xnode| matches!(xnode, XNode::Comment(_)))
+    }
+
+    pub fn pis(&self) -> impl Iterator<Item = Node> + '_ {
+        self.iter_kind(|xnode| matches!(xnode, XNode::ProcessingInstruction { .. }))
+    }
+
+    fn iter_kind(&self, kind: fn(&XNode) -> bool) -> impl Iterator<Item = Node> + '_ {
+        self.iter().filter(move |node| node.value(self).is_some_and(kind))
+    }
+
+    /// Iterate in postorder, every node comes after all of its descendants.
+    pub fn iter_postorder(&self) -> impl Iterator<Item = Node> + '_ {
+        PostOrder {
+            tree: self,
+            next: 0,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Every depth 1 node, in document order. Unlike `as_node().children()`
+    /// this needs no sibling walk and includes every root of a fragment
+    /// along with top level comments and PIs.
+    pub fn top_level_nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        self.depth
+            .iter()
+            .enumerate()
+            .filter(|(_, depth)| **depth == 1)
+            .map(|(index, _)| Node { index })
+    }
+
+    /// Element local names mapped to the attribute names seen on them,
+    /// namespace declarations left out. Elements without attributes map to
+    /// an empty set.
+    pub fn attribute_index(&self) -> BTreeMap<Box<str>, BTreeSet<Box<str>>> {
+        let mut index: BTreeMap<Box<str>, BTreeSet<Box<str>>> = BTreeMap::new();
+        for xnode in &self.nodes {
+            let XNode::Tag { name, attributes, .. } = xnode else {
+                continue;
+            };
+
+            let seen = index.entry(name.clone()).or_default();
+            for key in attributes.iter().flat_map(|a| a.keys()) {
+                if !is_namespace_declaration(key) {
+                    seen.insert(key.clone());
+                }
+            }
+        }
+        index
+    }
+
+    /// Local names of the elements at `depth`, each once in order of first
+    /// appearance. Depth 1 is the top level.
+    pub fn names_at_depth(&self, depth: u8) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (xnode, d) in self.nodes.iter().zip(&self.depth) {
+            if let XNode::Tag { name, .. } = xnode
+                && *d == depth
+                && !names.contains(&&**name)
+            {
+                names.push(name);
+            }
+        }
+        names
+    }
+
     /// Returns a `Node` cursor at the given index.
     pub fn node(&self, index: usize) -> Option<Node> {
         if index < self.len() {
@@ -83,29 +491,20 @@ impl FlatTree {
 
     // Looks for a node trying to figgure out if you have a s
     pub fn find_node(&self, target_name: &str) -> Option<Node> {
-        match target_name.contains(':') {
-            true => {
-                let mut split = target_name.split(':');
-
-                let ns = split.next()?;
-                let name = split.next()?;
-
+        match split_qname(target_name) {
+            (Some(ns), name) => {
                 let ns_index = self.find_namespace(Some(ns));
 
                 self.find_namespaced_node_by_name(ns_index, name)
             }
-            false => self.find_node_by_name(target_name),
+            (None, name) => self.find_node_by_name(name),
         }
     }
 
     /// Looks for a node with the name ignoring namespace.
     pub fn find_node_by_name(&self, target_name: &str) -> Option<Node> {
         for (i, xnode) in self.nodes.iter().enumerate() {
-            if let XNode::Tag {
-                namespace: _,
-                name,
-                attributes: _,
-            } = xnode
+            if let XNode::Tag { name, .. } = xnode
                 && *target_name == **name
             {
                 return Some(Node { index: i });
@@ -115,6 +514,29 @@ impl FlatTree {
         None
     }
 
+    /// Every element with the local name, in document order.
+    pub fn find_all_by_name(&self, target_name: &str) -> Vec<Node> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, xnode)| matches!(xnode, XNode::Tag { name, .. } if **name == *target_name))
+            .map(|(index, _)| Node { index })
+            .collect()
+    }
+
+    /// Index every element by local name, for trees that are queried over
+    /// and over. Lookups no longer scan the whole tree, but the index is a
+    /// snapshot: rebuild it after mutating the tree.
+    pub fn name_index(&self) -> NameIndex {
+        let mut names: BTreeMap<Box<str>, Vec<usize>> = BTreeMap::new();
+        for (index, xnode) in self.nodes.iter().enumerate() {
+            if let XNode::Tag { name, .. } = xnode {
+                names.entry(name.clone()).or_default().push(index);
+            }
+        }
+        NameIndex { names }
+    }
+
     /// Looks for a node with the name in the correct namespace.
     pub fn find_namespaced_node_by_name(
         &self,
@@ -125,7 +547,7 @@ impl FlatTree {
             if let XNode::Tag {
                 namespace,
                 name,
-                attributes: _,
+                ..
             } = xnode
                 && *target_name == **name
                 && target_namespace == *namespace
@@ -155,23 +577,285 @@ impl FlatTree {
         Node { index: position }
     }
 
-    // ── Namespace registry ──────────────────────────────────────────
+    /// Remove the last node. Nothing can hang under the last node in
+    /// preorder, so this never leaves a broken subtree behind.
+    pub fn pop(&mut self) -> Option<XNode> {
+        self.depth.pop()?;
+        self.spans.truncate(self.depth.len());
+        self.nodes.pop()
+    }
 
-    /// Register a namespace. Returns its u8 index, or `None` if the
-    /// registry is full (256 namespaces).
-    pub fn add_namespace(&mut self, prefix: Box<str>, uri: Box<str>) -> Option<u16> {
-        if self.namespace_map.contains_key(&prefix) {
-            let index = self.namespace_map.get(&prefix).unwrap();
-            return Some(*index as u16);
+    /// Mutable access to the xnode value at that index.
+    pub fn value_mut(&mut self, index: usize) -> Option<&mut XNode> {
+        self.nodes.get_mut(index)
+    }
+
+    /// Set an attribute on a tag, creating the attribute map if needed.
+    /// `name` is the qualified name, the prefix is resolved against the registry.
+    /// The `xml` prefix needs no declaration, it is registered on first use.
+    ///
+    /// Returns the previous value of the attribute. Does nothing if the
+    /// node is not a tag.
+    pub fn set_attribute(&mut self, node: &Node, name: &str, value: &str) -> Option<XAttribute> {
+        let namespace = match split_qname(name).0 {
+            Some("xml") => self.add_namespace("xml".into(), XML_NAMESPACE.into()),
+            prefix => self.find_namespace(prefix),
+        };
+
+        let Some(XNode::Tag { attributes, .. }) = self.nodes.get_mut(node.index) else {
+            return None;
+        };
+
+        attributes.get_or_insert_with(BTreeMap::new).insert(
+            name.into(),
+            XAttribute {
+                namespace,
+                value: value.into(),
+            },
+        )
+    }
+
+    /// Remove an attribute from a tag. The attribute map goes back to
+    /// `None` once the last attribute is removed.
+    pub fn remove_attribute(&mut self, node: &Node, name: &str) -> Option<XAttribute> {
+        let Some(XNode::Tag { attributes, .. }) = self.nodes.get_mut(node.index) else {
+            return None;
+        };
+
+        let map = attributes.as_mut()?;
+        let removed = map.remove(name);
+        if map.is_empty() {
+            *attributes = None;
+        }
+        removed
+    }
+
+    /// Merge runs of adjacent text siblings into a single text node. The
+    /// result is `Whitespace` only if everything merged into it was.
+    pub fn coalesce_text(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        let depth = std::mem::take(&mut self.depth);
+        self.spans.clear();
+
+        for (node, d) in nodes.into_iter().zip(depth) {
+            if let Some(text) = node.as_text()
+                && self.depth.last() == Some(&d)
+                && let Some(previous) = self.nodes.last_mut()
+                && let Some(before) = previous.as_text()
+            {
+                let merged = [before, text].concat().into_boxed_str();
+                *previous = match (&*previous, &node) {
+                    (XNode::Whitespace(_), XNode::Whitespace(_)) => XNode::Whitespace(merged),
+                    _ => XNode::Text(merged),
+                };
+                continue;
+            }
+            self.nodes.push(node);
+            self.depth.push(d);
+        }
+    }
+
+    /// Bring text into one form so that trees read from differently split or
+    /// formatted sources compare equal. Text is coalesced before line endings
+    /// are normalized, so a `\r\n` split across two nodes is one line break.
+    pub fn normalize(&mut self, options: NormalizeOptions) {
+        if options.coalesce_text {
+            self.coalesce_text();
+        }
+
+        if options.line_endings {
+            for xnode in &mut self.nodes {
+                if let XNode::Text(text) | XNode::Whitespace(text) = xnode
+                    && text.contains('\r')
+                {
+                    *text = text.replace("\r\n", "\n").replace('\r', "\n").into_boxed_str();
+                }
+            }
+        }
+
+        if options.trim_whitespace {
+            let ignorable: Vec<bool> = (0..self.len())
+                .map(|index| match &self.nodes[index] {
+                    XNode::Whitespace(_) => !Node { index }.preserves_space(self),
+                    XNode::Text(text) if text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r')) => {
+                        !Node { index }.preserves_space(self)
+                    }
+                    _ => false,
+                })
+                .collect();
+            if ignorable.contains(&true) {
+                let mut drop = ignorable.iter();
+                self.nodes.retain(|_| !drop.next().unwrap());
+                let mut drop = ignorable.iter();
+                self.depth.retain(|_| !drop.next().unwrap());
+                self.spans.clear();
+            }
+        }
+    }
+
+    /// Remove `node` and everything under it. Returns false for the sentinel
+    /// or a stale node.
+    pub fn remove_subtree(&mut self, node: &Node) -> bool {
+        if !node.is_valid(self) {
+            return false;
+        }
+        let range = node.index..node.subtree_end(self);
+
+        self.nodes.drain(range.clone());
+        self.depth.drain(range.clone());
+        // The other nodes still came from the same bytes.
+        if self.spans.len() >= range.end {
+            self.spans.drain(range);
+        } else {
+            self.spans.truncate(range.start);
+        }
+        true
+    }
+
+    /// Swap the subtree at `node` for the nodes of `replacement`, its top level
+    /// nodes taking the place of `node`. Namespaces are registered in this
+    /// tree and the replacement's ids remapped. Returns false, changing
+    /// nothing, for the sentinel or a stale node, if the replacement would go
+    /// past 255 levels, or if a namespace does not fit in the registry.
+    ///
+    /// Spans are kept up to `node` only. The new nodes have none and spans
+    /// cover a prefix of the nodes, so the ones after them are dropped too.
+    pub fn replace_subtree(&mut self, node: &Node, replacement: &FlatTree) -> bool {
+        if !node.is_valid(self) {
+            return false;
+        }
+        let range = node.index..node.subtree_end(self);
+        let base = self.depth[node.index] - 1;
+        if replacement.depth.iter().any(|d| d.checked_add(base).is_none()) {
+            return false;
         }
 
-        let id = self.namespaces.len();
-        if id > u16::MAX as usize {
+        let registered = self.namespaces.len();
+        let mut ids = Vec::with_capacity(replacement.namespaces.len());
+        for (prefix, uri) in &replacement.namespaces {
+            match self.add_namespace(prefix.clone(), (**uri).into()) {
+                Some(id) => ids.push(id),
+                None => {
+                    self.truncate_namespaces(registered);
+                    return false;
+                }
+            }
+        }
+        let remap = |namespace: &mut Option<u16>| *namespace = namespace.map(|id| ids[id as usize]);
+
+        let nodes = replacement.nodes.iter().cloned().map(|mut xnode| {
+            if let XNode::Tag { namespace, attributes, .. } = &mut xnode {
+                remap(namespace);
+                for attr in attributes.iter_mut().flat_map(|a| a.values_mut()) {
+                    remap(&mut attr.namespace);
+                }
+            }
+            xnode
+        });
+        self.nodes.splice(range.clone(), nodes);
+        self.depth.splice(range.clone(), replacement.depth.iter().map(|d| d + base));
+        self.spans.truncate(range.start);
+        true
+    }
+
+    /// Copy the subtree at `src` in as the last child of `dest_parent`, or as
+    /// the last top level node for the sentinel. `dest_parent` may be inside
+    /// `src`, the copy is taken before anything moves. Returns the copy, or
+    /// `None` for a stale node or if the copy would go past 255 levels.
+    pub fn duplicate_subtree(&mut self, src: &Node, dest_parent: &Node) -> Option<Node> {
+        if !src.is_valid(self) || !(dest_parent.is_sentinel() || dest_parent.is_valid(self)) {
+            return None;
+        }
+        let range = src.index..src.subtree_end(self);
+        let (at, parent_depth) = if dest_parent.is_sentinel() {
+            (self.len(), 0)
+        } else {
+            (dest_parent.subtree_end(self), self.depth[dest_parent.index])
+        };
+
+        let base = self.depth[src.index];
+        let mut depth = Vec::with_capacity(range.len());
+        for d in &self.depth[range.clone()] {
+            depth.push((d - base).checked_add(parent_depth)?.checked_add(1)?);
+        }
+        let nodes = self.nodes[range].to_vec();
+
+        self.nodes.splice(at..at, nodes);
+        self.depth.splice(at..at, depth);
+        self.spans.truncate(at);
+        Some(Node { index: at })
+    }
+
+    /// Move the subtree at `node` out into a tree of its own, with only the
+    /// namespaces it uses, see `Node::extract_subtree`.
+    pub fn detach(&mut self, node: &Node) -> FlatTree {
+        let detached = node.extract_subtree(self);
+        self.remove_subtree(node);
+        detached
+    }
+
+    /// Remove every comment and PI in one pass. Both are always leaves, so
+    /// the depths of everything that stays are still right.
+    pub fn strip_non_element_noise(&mut self) {
+        let keep = |xnode: &XNode| !matches!(xnode, XNode::Comment(_) | XNode::ProcessingInstruction { .. });
+
+        let mut kept = self.nodes.iter().map(keep).collect::<Vec<_>>().into_iter();
+        self.depth.retain(|_| kept.next().unwrap_or(true));
+        self.nodes.retain(keep);
+        self.spans.clear();
+    }
+
+    // ── Namespace registry ──────────────────────────────────────────
+
+    /// Register a prefix to uri binding. Returns its id, the existing one if
+    /// the same binding was registered before, or `None` if the registry is
+    /// full (65536 namespaces).
+    ///
+    /// An empty uri is not a namespace: `xmlns=""` undeclares the default and
+    /// elements under it have no namespace at all, so nothing is registered
+    /// and the result is `None` as well. The declaration itself stays on the
+    /// tag, that is what the canonicalizer scopes by.
+    ///
+    /// `xml` can only be bound to `XML_NAMESPACE` and that uri to no other
+    /// prefix, anything else is refused with `None`.
+    pub fn add_namespace(&mut self, prefix: Box<str>, uri: Box<str>) -> Option<u16> {
+        if uri.is_empty() || ((&*prefix == "xml") != (&*uri == XML_NAMESPACE)) {
             return None;
         }
+        if let Some(id) = self.find_namespace_binding(&prefix, &uri) {
+            return Some(id);
+        }
+
+        // The next id is the current length, the last one that fits is
+        // `u16::MAX` with the registry then holding 65536 entries.
+        let id = u16::try_from(self.namespaces.len()).ok()?;
+        // Long uris repeat under different prefixes, store each one once.
+        let uri = match self.namespaces.iter().find(|(_, known)| **known == *uri) {
+            Some((_, known)) => known.clone(),
+            None => uri.into(),
+        };
         self.namespaces.push((prefix.clone(), uri));
-        self.namespace_map.insert(prefix, id);
-        Some(id as u16)
+        self.namespace_map.entry(prefix).or_default().push(id);
+        Some(id)
+    }
+
+    /// Drop the registry entries from `len` on, undoing the registrations made
+    /// since the registry had that length.
+    fn truncate_namespaces(&mut self, len: usize) {
+        self.namespaces.truncate(len);
+        self.namespace_map.retain(|_, ids| {
+            ids.retain(|id| usize::from(*id) < len);
+            !ids.is_empty()
+        });
+    }
+
+    /// The id of one exact prefix to uri binding.
+    pub fn find_namespace_binding(&self, prefix: &str, uri: &str) -> Option<u16> {
+        self.namespace_map
+            .get(prefix)?
+            .iter()
+            .find(|id| *self.namespaces[usize::from(**id)].1 == *uri)
+            .copied()
     }
 
     /// Look up a namespace by its id.
@@ -183,11 +867,204 @@ impl FlatTree {
           .map(|(p, u)| (p.as_ref(), u.as_ref()))
     }
 
-    /// Find a namespace id by its prefix.
+    /// Number of registered namespaces.
+    pub fn namespace_count(&self) -> usize {
+        self.namespaces.len()
+    }
+
+    /// Just the uri of a namespace id, `None` for no namespace.
+    pub fn namespace_uri(&self, id: Option<u16>) -> Option<&str> {
+        self.get_namespace(id).map(|(_, uri)| uri)
+    }
+
+    /// Rename a registered prefix, keeping its id so every node referencing
+    /// it stays valid. Declarations and prefixed attribute names are renamed
+    /// along with it.
+    ///
+    /// Returns false and changes nothing if `old` is not registered or `new`
+    /// is already bound to a different uri. The default namespace (`""`) and
+    /// the reserved `xml` prefix can not be remapped either way, unprefixed
+    /// attributes have no namespace so the default is not just another name.
+    pub fn remap_prefix(&mut self, old: &str, new: &str) -> bool {
+        if [old, new].iter().any(|prefix| prefix.is_empty() || *prefix == "xml") {
+            return false;
+        }
+        let Some(ids) = self.namespace_map.get(old) else {
+            return false;
+        };
+        if let Some(existing) = self.namespace_map.get(new)
+            && existing.iter().any(|e| ids.iter().all(|id| self.namespaces[usize::from(*e)].1 != self.namespaces[usize::from(*id)].1))
+        {
+            return false;
+        }
+
+        let ids = self.namespace_map.remove(old).unwrap();
+        for id in &ids {
+            self.namespaces[usize::from(*id)].0 = new.into();
+        }
+        self.namespace_map.entry(new.into()).or_default().extend(&ids);
+
+        let old_declaration = format!("xmlns:{old}");
+        for xnode in &mut self.nodes {
+            let XNode::Tag {
+                attributes: Some(attributes),
+                ..
+            } = xnode
+            else {
+                continue;
+            };
+
+            let renamed: Vec<Box<str>> = attributes
+                .iter()
+                .filter(|(key, attr)| {
+                    let key: &str = key;
+                    key == old_declaration
+                        || (attr.namespace.is_some_and(|ns| ids.contains(&ns))
+                            && split_qname(key).0 == Some(old))
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in renamed {
+                let attr = attributes.remove(&key).unwrap();
+                let local = split_qname(&key).1;
+                let key = if *key == *old_declaration {
+                    format!("xmlns:{new}")
+                } else {
+                    format!("{new}:{local}")
+                };
+                attributes.insert(key.into_boxed_str(), attr);
+            }
+        }
+
+        true
+    }
+
+    /// Iterate over the registry as (id, prefix, uri).
+    pub fn namespaces(&self) -> impl Iterator<Item = (u16, &str, &str)> {
+      (0..=u16::MAX)
+          .zip(&self.namespaces)
+          .map(|(i, (p, u))| (i, p.as_ref(), u.as_ref()))
+    }
+
+    /// Registry ids some element or attribute refers to. Declarations alone do
+    /// not count, a namespace that is declared but never used is left out.
+    pub fn used_namespaces(&self) -> BTreeSet<u16> {
+        used_namespaces(&self.nodes)
+    }
+
+    /// Drop the registry entries no node refers to and renumber the rest
+    /// densely, keeping their order. Declarations stay on the tags, but a
+    /// prefix that was only declared no longer resolves in `set_attribute`.
+    pub fn compact_namespaces(&mut self) {
+        let used = used_namespaces(&self.nodes);
+        if used.len() == self.namespaces.len() {
+            return;
+        }
+
+        let namespaces = std::mem::take(&mut self.namespaces);
+        self.namespace_map.clear();
+        let mut ids = BTreeMap::new();
+        for (old, binding) in (0..=u16::MAX).zip(namespaces) {
+            if used.contains(&old) {
+                let new = u16::try_from(self.namespaces.len()).expect("compacting does not grow the registry");
+                self.namespace_map.entry(binding.0.clone()).or_default().push(new);
+                self.namespaces.push(binding);
+                ids.insert(old, new);
+            }
+        }
+
+        let remap = |namespace: &mut Option<u16>| *namespace = namespace.and_then(|id| ids.get(&id).copied());
+        for xnode in &mut self.nodes {
+            if let XNode::Tag { namespace, attributes, .. } = xnode {
+                remap(namespace);
+                for attr in attributes.iter_mut().flat_map(BTreeMap::values_mut) {
+                    remap(&mut attr.namespace);
+                }
+            }
+        }
+    }
+
+    /// Find a namespace id by its prefix. With the prefix bound more than
+    /// once this is the first binding, see `find_namespace_binding`.
     pub fn find_namespace(&self, prefix: Option<&str>) -> Option<u16> {
       prefix?;
 
-      self.namespace_map.get(prefix.unwrap()).map(|i| *i as u16)
+      self.namespace_map.get(prefix.unwrap()).and_then(|ids| ids.first()).copied()
+    }
+}
+
+fn used_namespaces(nodes: &[XNode]) -> BTreeSet<u16> {
+    let mut used = BTreeSet::new();
+    for xnode in nodes {
+        if let XNode::Tag { namespace, attributes, .. } = xnode {
+            used.extend(*namespace);
+            used.extend(attributes.iter().flat_map(|a| a.values()).filter_map(|a| a.namespace));
+        }
+    }
+    used
+}
+
+/// Element positions by local name, see `FlatTree::name_index`.
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    names: BTreeMap<Box<str>, Vec<usize>>,
+}
+
+impl NameIndex {
+    /// Same result as `FlatTree::find_all_by_name` on the indexed tree.
+    pub fn find_all(&self, name: &str) -> impl Iterator<Item = Node> + '_ {
+        self.names
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|index| Node { index: *index })
+    }
+
+    /// First element with the name, like `FlatTree::find_node_by_name`.
+    pub fn find_first(&self, name: &str) -> Option<Node> {
+        self.find_all(name).next()
+    }
+}
+
+/// The name of `node` as an XPath step, `prefix:name` or a node test.
+fn step_name(tree: &FlatTree, node: &Node) -> String {
+    match node.value(tree) {
+        Some(XNode::Tag { namespace, name, .. }) => match tree.get_namespace(*namespace) {
+            Some((prefix, _)) if !prefix.is_empty() => format!("{prefix}:{name}"),
+            _ => name.to_string(),
+        },
+        Some(XNode::Text(_) | XNode::Whitespace(_)) => "text()".to_string(),
+        Some(XNode::Comment(_)) => "comment()".to_string(),
+        Some(XNode::ProcessingInstruction { .. }) => "processing-instruction()".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Walks the preorder arrays keeping a stack of nodes whose subtree has not
+/// ended yet, a node is yielded once something at its depth or above shows up.
+struct PostOrder<'a> {
+    tree: &'a FlatTree,
+    next: usize,
+    stack: Vec<usize>,
+}
+
+impl Iterator for PostOrder<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        while self.next < self.tree.len() {
+            let depth = self.tree.depth[self.next];
+            if let Some(&top) = self.stack.last()
+                && self.tree.depth[top] >= depth
+            {
+                self.stack.pop();
+                return Some(Node { index: top });
+            }
+            self.stack.push(self.next);
+            self.next += 1;
+        }
+        self.stack.pop().map(|index| Node { index })
     }
 }
 
@@ -208,12 +1085,18 @@ impl Default for FlatTree {
 // Well I initally implemented it with a refrence to the source tree.
 // It made the API better to work with but, it also added a bunch of borrows or refrences to the flattree
 // And i did not like how that added alot more lifetime tracking so i removed it.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Node {
     index: usize,
 }
 
 impl Node {
+    /// True if both cursors point at the same position, two sentinels are
+    /// the same node. Says nothing about whether they come from one tree.
+    pub fn same_as(&self, other: &Node) -> bool {
+        self.index == other.index
+    }
+
     pub fn is_sentinel(&self) -> bool {
         self.index == usize::MAX
     }
@@ -238,18 +1121,70 @@ impl Node {
         tree.value(self.index)
     }
 
+    /// An owned copy of `value`, to push into another tree. Namespace ids
+    /// still refer to this tree's registry.
+    pub fn clone_value(&self, tree: &FlatTree) -> Option<XNode> {
+        self.value(tree).cloned()
+    }
+
+    /// Byte range of this node in the source it was read from, if the
+    /// reader recorded spans and the tree has not been restructured since.
+    pub fn source_span(&self, tree: &FlatTree) -> Option<(usize, usize)> {
+        if self.is_sentinel() {
+            return None;
+        }
+        tree.spans.get(self.index).copied()
+    }
+
+    /// Stream the canonical form of this subtree, without comments, into
+    /// `w`, a file or a hasher for example. `canonical::write_canonical` is
+    /// the same with options.
+    pub fn write_canonical<W: std::io::Write>(&self, tree: &FlatTree, w: &mut W) -> std::io::Result<()> {
+        crate::write_canonical(tree, self, &crate::CanonicalOptions::default(), w)
+    }
+
+    /// `write_canonical` into a string, the same as `canonicalize(tree, self)`.
+    pub fn canonicalize(&self, tree: &FlatTree) -> String {
+        crate::canonicalize(tree, self)
+    }
+
     pub fn push(&self, tree: &mut FlatTree, node: XNode) -> Node {
         let depth = self.depth(tree) + 1;
         tree.push_depth(node, depth)
     }
 
-    pub fn compare_name(&self, tree: &FlatTree, target_namespace: Option<u16>, target_name: &str) -> bool{
-      let node = tree.value(self.index);
-      match node {
-        Some(node) => {
+    /// Push a `prefix:name` tag bound to `uri`, registering the namespace if
+    /// it is new. The tag also gets the `xmlns` declaration unless the same
+    /// binding is already in scope here. An empty prefix is the default
+    /// namespace. If the registry is full the tag has no namespace.
+    pub fn push_tag_ns(&self, tree: &mut FlatTree, prefix: &str, uri: &str, name: &str) -> Node {
+        let namespace = tree.add_namespace(prefix.into(), uri.into());
+        let binding = ((!prefix.is_empty()).then_some(prefix), uri);
+        let declared = self.in_scope_namespaces(tree).contains(&binding);
+
+        let node = self.push(
+            tree,
+            XNode::Tag {
+                namespace,
+                name: name.into(),
+                attributes: None,
+                self_closing: false,
+            },
+        );
+        if namespace.is_some() && !declared {
+            let key = if prefix.is_empty() { "xmlns".to_string() } else { format!("xmlns:{prefix}") };
+            tree.set_attribute(&node, &key, uri);
+        }
+        node
+    }
+
+    pub fn compare_name(&self, tree: &FlatTree, target_namespace: Option<u16>, target_name: &str) -> bool{
+      let node = tree.value(self.index);
+      match node {
+        Some(node) => {
           match node {
-            XNode::Tag { namespace, name, attributes: _ } => target_namespace == *namespace && *target_name == **name,
-            XNode::Text(_) => false,
+            XNode::Tag { namespace, name, .. } => target_namespace == *namespace && *target_name == **name,
+            XNode::Text(_) | XNode::Whitespace(_) => false,
             XNode::Comment(_) => false,
             XNode::ProcessingInstruction { target: _, data: _ } => false,
           }
@@ -258,6 +1193,109 @@ impl Node {
       }
     }
 
+    /// `compare_name` that also requires each of `attrs` (qualified name, value)
+    /// to be present on the tag with that value.
+    pub fn matches(
+        &self,
+        tree: &FlatTree,
+        namespace: Option<u16>,
+        name: &str,
+        attrs: &[(&str, &str)],
+    ) -> bool {
+        if !self.compare_name(tree, namespace, name) {
+            return false;
+        }
+        let Some(XNode::Tag { attributes, .. }) = self.value(tree) else {
+            return false;
+        };
+
+        attrs.iter().all(|(key, value)| {
+            attributes
+                .as_ref()
+                .and_then(|a| a.get(*key))
+                .is_some_and(|attr| &*attr.value == *value)
+        })
+    }
+
+    /// The attributes of a tag keyed by qualified name, declarations included.
+    /// A tag with `attributes: None` and one with an empty map look the same
+    /// here, and anything that is not a tag has none.
+    pub fn attributes<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = (&'a str, &'a XAttribute)> + use<'a> {
+        let attributes = match self.value(tree) {
+            Some(XNode::Tag { attributes, .. }) => attributes.as_ref(),
+            _ => None,
+        };
+        attributes.into_iter().flatten().map(|(key, attr)| (&**key, attr))
+    }
+
+    /// The values of several attributes by qualified name in one call, in the
+    /// order of `names`, `None` where the tag does not have one.
+    pub fn attributes_as<'a, const N: usize>(&self, tree: &'a FlatTree, names: &[&str; N]) -> [Option<&'a str>; N] {
+        let attributes = match self.value(tree) {
+            Some(XNode::Tag { attributes: Some(attributes), .. }) => Some(attributes),
+            _ => None,
+        };
+        names.map(|name| attributes.and_then(|a| a.get(name)).map(|attr| &*attr.value))
+    }
+
+    /// Number of `attributes`, 0 for `None` and for an empty map alike.
+    pub fn attribute_count(&self, tree: &FlatTree) -> usize {
+        match self.value(tree) {
+            Some(XNode::Tag { attributes: Some(attributes), .. }) => attributes.len(),
+            _ => 0,
+        }
+    }
+
+    /// Attribute by namespace id and local name, so `ns:attr` and `attr` on the
+    /// same tag are told apart. Namespace declarations are not matched.
+    pub fn attribute_ns<'a>(
+        &self,
+        tree: &'a FlatTree,
+        namespace: Option<u16>,
+        name: &str,
+    ) -> Option<&'a XAttribute> {
+        let Some(XNode::Tag {
+            attributes: Some(attributes),
+            ..
+        }) = self.value(tree)
+        else {
+            return None;
+        };
+
+        attributes.iter().find_map(|(key, attr)| {
+            let local = split_qname(key).1;
+
+            (!is_namespace_declaration(key) && attr.namespace == namespace && local == name)
+                .then_some(attr)
+        })
+    }
+
+    /// Attribute by namespace uri and local name, whatever prefix the document
+    /// declared for it. Every binding of the uri counts, not just the first.
+    pub fn attribute_by_uri<'a>(
+        &self,
+        tree: &'a FlatTree,
+        uri: &str,
+        name: &str,
+    ) -> Option<&'a XAttribute> {
+        let Some(XNode::Tag {
+            attributes: Some(attributes),
+            ..
+        }) = self.value(tree)
+        else {
+            return None;
+        };
+
+        attributes.iter().find_map(|(key, attr)| {
+            let local = split_qname(key).1;
+
+            (!is_namespace_declaration(key)
+                && local == name
+                && tree.namespace_uri(attr.namespace) == Some(uri))
+            .then_some(attr)
+        })
+    }
+
     /// Scan backward to find the parent (first node with depth == self.depth - 1).
     pub fn parent(&self, tree: &FlatTree) -> Option<Node> {
         let d = self.depth(tree);
@@ -275,6 +1313,73 @@ impl Node {
         None
     }
 
+    /// `parent` with the reason there is none.
+    pub fn try_parent(&self, tree: &FlatTree) -> Result<Node, NavError> {
+        if self.is_sentinel() {
+            return Err(NavError::Sentinel);
+        }
+        if !self.is_valid(tree) {
+            return Err(NavError::Stale);
+        }
+        self.parent(tree).ok_or(NavError::IsRoot)
+    }
+
+    /// True if the node has at least one child, without collecting them.
+    pub fn has_children(&self, tree: &FlatTree) -> bool {
+        if !self.is_valid(tree) {
+            return false;
+        }
+        let next = self.index + 1;
+        next < tree.len() && tree.depth[next] == tree.depth[self.index] + 1
+    }
+
+    /// True if the node has no children. Text, comments and PIs always are.
+    pub fn is_leaf(&self, tree: &FlatTree) -> bool {
+        !self.has_children(tree)
+    }
+
+    /// The text of an element whose only child is a single text node, as in
+    /// `<id>42</id>`. `None` for anything else, including empty elements.
+    pub fn as_text_value<'a>(&self, tree: &'a FlatTree) -> Option<&'a str> {
+        if !matches!(self.value(tree), Some(XNode::Tag { .. })) || self.subtree_end(tree) != self.index + 2 {
+            return None;
+        }
+        tree.value(self.index + 1).and_then(XNode::as_text)
+    }
+
+    /// All text in the subtree in document order, the node's own text for a
+    /// text node. The document sentinel covers the whole tree.
+    pub fn text_content(&self, tree: &FlatTree) -> String {
+        self.text_content_joined(tree, "")
+    }
+
+    /// `text_content` with `sep` between separate text nodes, for mixed
+    /// content where child elements break a value into runs. Text is stored
+    /// unescaped, so the result is plain text and not XML.
+    pub fn text_content_joined(&self, tree: &FlatTree, sep: &str) -> String {
+        let texts: Vec<&str> = self.texts(tree).collect();
+        texts.join(sep)
+    }
+
+    /// `text_content().len()` without building the string. CDATA sections are
+    /// read as text and count the same.
+    pub fn text_len(&self, tree: &FlatTree) -> usize {
+        self.texts(tree).map(str::len).sum()
+    }
+
+    /// The text nodes of the subtree, the whole tree for the sentinel.
+    fn texts<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = &'a str> + use<'a> {
+        let range = if self.is_sentinel() {
+            0..tree.len()
+        } else if self.is_valid(tree) {
+            self.index..self.subtree_end(tree)
+        } else {
+            0..0
+        };
+
+        tree.nodes[range].iter().filter_map(XNode::as_text)
+    }
+
     /// Collect direct children (depth == self.depth + 1 within the subtree).
     pub fn children(&self, tree: &FlatTree) -> Vec<Node> {
         if !self.is_valid(tree) {
@@ -293,6 +1398,40 @@ impl Node {
         result
     }
 
+    /// Descendants exactly `relative` levels below this node
+    /// (1 is the children, 2 the grandchildren and so on).
+    pub fn descendants_at_depth(&self, tree: &FlatTree, relative: u8) -> Vec<Node> {
+        self.iter_descendants_at_depth(tree, relative).collect()
+    }
+
+    /// The first child element named `name`, ignoring namespaces like
+    /// `FlatTree::find_node_by_name`. Deeper elements are not looked at, and
+    /// for the document sentinel the children are the top level nodes.
+    pub fn find_child_by_name(&self, tree: &FlatTree, name: &str) -> Option<Node> {
+        let is_named = |node: &Node| matches!(node.value(tree), Some(XNode::Tag { name: n, .. }) if **n == *name);
+        if self.is_sentinel() {
+            tree.top_level_nodes().find(is_named)
+        } else {
+            self.iter_descendants_at_depth(tree, 1).find(is_named)
+        }
+    }
+
+    /// Iterator version of `descendants_at_depth`.
+    pub fn iter_descendants_at_depth<'a>(
+        &self,
+        tree: &'a FlatTree,
+        relative: u8,
+    ) -> impl Iterator<Item = Node> + 'a {
+        let target = self.depth(tree).checked_add(relative);
+        let range = match target {
+            Some(_) if relative > 0 && self.is_valid(tree) => self.index + 1..self.subtree_end(tree),
+            _ => 0..0,
+        };
+        range
+            .filter(move |i| Some(tree.depth[*i]) == target)
+            .map(|i| Node { index: i })
+    }
+
     /// Next sibling: first node after this subtree with the same depth.
     pub fn next_sibling(&self, tree: &FlatTree) -> Option<Node> {
         if !self.is_valid(tree) {
@@ -330,6 +1469,44 @@ impl Node {
         }
     }
 
+    /// The siblings after this node, nearest first.
+    pub fn following_siblings<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = Node> + use<'a> {
+        std::iter::successors(self.next_sibling(tree), move |node| node.next_sibling(tree))
+    }
+
+    /// The siblings before this node, nearest first.
+    pub fn preceding_siblings<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = Node> + use<'a> {
+        std::iter::successors(self.prev_sibling(tree), move |node| node.prev_sibling(tree))
+    }
+
+    /// True if `other` is inside this node's subtree, not counting the node
+    /// itself. The document sentinel is an ancestor of every node.
+    pub fn is_ancestor_of(&self, tree: &FlatTree, other: &Node) -> bool {
+        if !other.is_valid(tree) {
+            return false;
+        }
+        if self.is_sentinel() {
+            return true;
+        }
+        self.index < other.index && other.index < self.subtree_end(tree)
+    }
+
+    /// `other.is_ancestor_of(self)`.
+    pub fn is_descendant_of(&self, tree: &FlatTree, other: &Node) -> bool {
+        other.is_ancestor_of(tree, self)
+    }
+
+    /// Whether the nearest `xml:space` on an ancestor is `preserve`.
+    fn preserves_space(&self, tree: &FlatTree) -> bool {
+        self.ancestors(tree)
+            .iter()
+            .find_map(|ancestor| match ancestor.value(tree) {
+                Some(XNode::Tag { attributes: Some(attributes), .. }) => attributes.get("xml:space"),
+                _ => None,
+            })
+            .is_some_and(|space| &*space.value == "preserve")
+    }
+
     /// All ancestors (parent, grandparent, … root).
     pub fn ancestors(&self, tree: &FlatTree) -> Vec<Node> {
         let mut result = Vec::new();
@@ -341,6 +1518,86 @@ impl Node {
         result
     }
 
+    /// This node, then its ancestors nearest first.
+    pub fn ancestor_or_self<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = Node> + use<'a> {
+        std::iter::successors(Some(self.clone()), move |node| node.parent(tree))
+    }
+
+    /// The (prefix, uri) bindings visible at this node from the declarations
+    /// on it and its ancestors, `None` for the default namespace, sorted by
+    /// prefix. Nearer declarations win and an empty uri undeclares.
+    pub fn in_scope_namespaces<'a>(&self, tree: &'a FlatTree) -> Vec<(Option<&'a str>, &'a str)> {
+        let mut path = self.ancestors(tree);
+        path.reverse();
+        path.push(self.clone());
+
+        let mut scope: BTreeMap<&str, &str> = BTreeMap::new();
+        for node in &path {
+            let Some(XNode::Tag { attributes: Some(attributes), .. }) = node.value(tree) else {
+                continue;
+            };
+            for (key, attr) in attributes {
+                let prefix = if &**key == "xmlns" { Some("") } else { key.strip_prefix("xmlns:") };
+                match prefix {
+                    Some(prefix) if attr.value.is_empty() => _ = scope.remove(prefix),
+                    Some(prefix) => _ = scope.insert(prefix, &attr.value),
+                    None => {}
+                }
+            }
+        }
+
+        scope
+            .into_iter()
+            .map(|(prefix, uri)| ((!prefix.is_empty()).then_some(prefix), uri))
+            .collect()
+    }
+
+    /// Absolute path to this node like `/root/child[1]/text()[1]`, for
+    /// logging and error messages.
+    ///
+    /// Each step is numbered among the siblings with the same step name.
+    /// Top level steps only get a number when the name is not unique there.
+    /// Prefixed names use the prefix from the registry, the sentinel is `/`.
+    pub fn xpath(&self, tree: &FlatTree) -> String {
+        if !self.is_valid(tree) {
+            return "/".to_string();
+        }
+
+        let mut steps = self.ancestors(tree);
+        steps.reverse();
+        steps.push(self.clone());
+
+        let mut path = String::new();
+        for step in &steps {
+            let name = step_name(tree, step);
+            let same = |node: &Node| step_name(tree, node) == name;
+
+            let mut position = 1;
+            let mut current = step.prev_sibling(tree);
+            while let Some(sibling) = current {
+                position += usize::from(same(&sibling));
+                current = sibling.prev_sibling(tree);
+            }
+
+            path.push('/');
+            path.push_str(&name);
+            let unique = || {
+                let mut next = step.next_sibling(tree);
+                while let Some(sibling) = next {
+                    if same(&sibling) {
+                        return false;
+                    }
+                    next = sibling.next_sibling(tree);
+                }
+                true
+            };
+            if step.depth(tree) > 1 || position > 1 || !unique() {
+                path.push_str(&format!("[{position}]"));
+            }
+        }
+        path
+    }
+
     /// All descendants (contiguous slice after self with depth > self.depth).
     pub fn descendants(&self, tree: &FlatTree) -> Vec<Node> {
         if !self.is_valid(tree) {
@@ -350,6 +1607,28 @@ impl Node {
         (self.index + 1..end).map(|i| Node { index: i }).collect()
     }
 
+    /// This node, then its descendants in document order. For the document
+    /// sentinel that is the sentinel and every node, for a stale node nothing.
+    pub fn descendant_or_self<'a>(&self, tree: &'a FlatTree) -> impl Iterator<Item = Node> + use<'a> {
+        let (this, range) = if self.is_sentinel() {
+            (Some(self.clone()), 0..tree.len())
+        } else if self.is_valid(tree) {
+            (Some(self.clone()), self.index + 1..self.subtree_end(tree))
+        } else {
+            (None, 0..0)
+        };
+        this.into_iter().chain(range.map(|index| Node { index }))
+    }
+
+    /// `descendants().len()` without collecting them, 0 where `descendants`
+    /// is empty.
+    pub fn descendant_count(&self, tree: &FlatTree) -> usize {
+        if !self.is_valid(tree) {
+            return 0;
+        }
+        self.subtree_end(tree) - self.index - 1
+    }
+
     /// Index one past the last descendant of this node.
     pub fn subtree_end(&self, tree: &FlatTree) -> usize {
         if !self.is_valid(tree) {
@@ -362,6 +1641,89 @@ impl Node {
         }
         i
     }
+
+    /// The last node of the subtree in preorder, `None` for a leaf. The
+    /// document sentinel gives the last node of the tree.
+    pub fn last_descendant(&self, tree: &FlatTree) -> Option<Node> {
+        let end = if self.is_sentinel() {
+            tree.len()
+        } else {
+            self.subtree_end(tree)
+        };
+        let last = end.checked_sub(1)?;
+        (self.is_sentinel() || last > self.index).then_some(Node { index: last })
+    }
+
+    /// The `nodes` and `depth` slices of this subtree, this node first. Depths
+    /// are absolute, not relative to this node. Empty for the sentinel.
+    pub fn subtree_slice<'a>(&self, tree: &'a FlatTree) -> (&'a [XNode], &'a [u8]) {
+        if !self.is_valid(tree) {
+            return (&[], &[]);
+        }
+        let range = self.index..self.subtree_end(tree);
+        (&tree.nodes[range.clone()], &tree.depth[range])
+    }
+
+    /// Copy this subtree into a tree of its own, registering only the
+    /// namespaces used by an element or attribute inside it.
+    ///
+    /// Declarations of anything else are dropped, and the used namespaces
+    /// are declared on the new root so it reads on its own.
+    pub fn extract_subtree(&self, tree: &FlatTree) -> FlatTree {
+        let mut extracted = FlatTree::new();
+        extracted.raw_text = tree.raw_text;
+        if !self.is_valid(tree) {
+            return extracted;
+        }
+        let range = self.index..self.subtree_end(tree);
+
+        let used = used_namespaces(&tree.nodes[range.clone()]);
+
+        let mut ids = BTreeMap::new();
+        for id in &used {
+            if let Some((prefix, uri)) = tree.get_namespace(Some(*id))
+                && let Some(new_id) = extracted.add_namespace(prefix.into(), uri.into())
+            {
+                ids.insert(*id, new_id);
+            }
+        }
+        let remap = |namespace: Option<u16>| namespace.and_then(|id| ids.get(&id).copied());
+
+        let base = tree.depth[self.index] - 1;
+        for index in range.clone() {
+            let mut xnode = tree.nodes[index].clone();
+            if let XNode::Tag { namespace, attributes, .. } = &mut xnode {
+                *namespace = remap(*namespace);
+
+                if let Some(map) = attributes {
+                    map.retain(|key, _| {
+                        let declared = if &**key == "xmlns" { Some("") } else { key.strip_prefix("xmlns:") };
+                        declared.is_none_or(|prefix| extracted.find_namespace(Some(prefix)).is_some())
+                    });
+                    for attr in map.values_mut() {
+                        attr.namespace = remap(attr.namespace);
+                    }
+                }
+
+                if index == range.start {
+                    let map = attributes.get_or_insert_with(BTreeMap::new);
+                    // `xml` is bound without a declaration, and may not be declared otherwise.
+                    for (prefix, uri) in extracted.namespaces.iter().filter(|(prefix, _)| &**prefix != "xml") {
+                        let key = if prefix.is_empty() { "xmlns".into() } else { format!("xmlns:{prefix}") };
+                        map.entry(key.into_boxed_str()).or_insert_with(|| XAttribute {
+                            namespace: None,
+                            value: (**uri).into(),
+                        });
+                    }
+                    if map.is_empty() {
+                        *attributes = None;
+                    }
+                }
+            }
+            extracted.push_depth(xnode, tree.depth[index] - base);
+        }
+        extracted
+    }
 }
 
 #[cfg(test)]
@@ -387,6 +1749,7 @@ mod tests {
                 namespace: None,
                 name: "root".into(),
                 attributes: None,
+                self_closing: false,
             },
         );
 
@@ -405,6 +1768,7 @@ mod tests {
                 namespace: None,
                 name: "child".into(),
                 attributes: Some(attrs),
+                self_closing: false,
             },
         );
 
@@ -436,6 +1800,7 @@ mod tests {
                 namespace: None,
                 name: "root1".into(),
                 attributes: None,
+                self_closing: false,
             },
         );
 
@@ -446,6 +1811,7 @@ mod tests {
                 namespace: None,
                 name: "root2".into(),
                 attributes: None,
+                self_closing: false,
             },
         );
 
@@ -461,6 +1827,23 @@ mod tests {
         assert_eq!(vector, [1, 2, 3, 2]);
     }
 
+    #[test]
+    fn top_level_nodes() {
+        let tree = empty_tree();
+        let names: Vec<_> = tree
+            .top_level_nodes()
+            .filter_map(|n| match n.value(&tree) {
+                Some(XNode::Tag { name, .. }) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["root1", "root2"]);
+
+        let tree = sample_tree();
+        let top: Vec<_> = tree.top_level_nodes().map(|n| n.index()).collect();
+        assert_eq!(top, [0]);
+    }
+
     #[test]
     fn depth_alignment_empty() {
         let tree = empty_tree();
@@ -470,6 +1853,165 @@ mod tests {
         assert_eq!(vector, [1, 1]);
     }
 
+    #[test]
+    fn node_counts() {
+        let tree = sample_tree();
+        assert_eq!(
+            tree.node_counts(),
+            NodeCounts {
+                tags: 2,
+                text: 1,
+                comments: 1,
+                pis: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn matches_name_and_attributes() {
+        let tree = sample_tree();
+        let child = tree.node(1).unwrap();
+
+        assert!(child.matches(&tree, None, "child", &[]));
+        assert!(child.matches(&tree, None, "child", &[("attr", "val")]));
+        assert!(!child.matches(&tree, None, "child", &[("attr", "other")]));
+        assert!(!child.matches(&tree, None, "child", &[("missing", "val")]));
+        assert!(!child.matches(&tree, None, "root", &[("attr", "val")]));
+        assert!(!tree.node(2).unwrap().matches(&tree, None, "child", &[]));
+    }
+
+    #[test]
+    fn attribute_by_namespace() {
+        let mut tree = sample_tree();
+        let ns = tree.add_namespace("ns".into(), "http://ns.example.com".into());
+        let child = tree.node(1).unwrap();
+        tree.set_attribute(&child, "ns:attr", "prefixed");
+
+        assert_eq!(&*child.attribute_ns(&tree, None, "attr").unwrap().value, "val");
+        assert_eq!(&*child.attribute_ns(&tree, ns, "attr").unwrap().value, "prefixed");
+        assert!(child.attribute_ns(&tree, ns, "missing").is_none());
+        assert!(tree.node(0).unwrap().attribute_ns(&tree, None, "attr").is_none());
+    }
+
+    #[test]
+    fn attribute_count() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        let child = tree.node(1).unwrap();
+        assert_eq!(child.attribute_count(&tree), 1);
+        assert_eq!(child.attributes(&tree).map(|(key, _)| key).collect::<Vec<_>>(), ["attr"]);
+
+        // `None` and an empty map are the same.
+        assert_eq!(root.attribute_count(&tree), 0);
+        if let Some(XNode::Tag { attributes, .. }) = tree.value_mut(1) {
+            *attributes = Some(BTreeMap::new());
+        }
+        assert_eq!(child.attribute_count(&tree), 0);
+        assert_eq!(child.attributes(&tree).count(), 0);
+        assert_eq!(tree.node(2).unwrap().attribute_count(&tree), 0);
+        assert_eq!(tree.document().attributes(&tree).count(), 0);
+    }
+
+    #[test]
+    fn attribute_by_uri() {
+        for prefix in ["a", "b"] {
+            let mut tree = sample_tree();
+            tree.add_namespace(prefix.into(), "urn:shared".into());
+            let child = tree.node(1).unwrap();
+            tree.set_attribute(&child, &format!("{prefix}:attr"), prefix);
+
+            let attr = child.attribute_by_uri(&tree, "urn:shared", "attr").unwrap();
+            assert_eq!(&*attr.value, prefix);
+            assert!(child.attribute_by_uri(&tree, "urn:other", "attr").is_none());
+            assert!(child.attribute_by_uri(&tree, "urn:shared", "missing").is_none());
+        }
+    }
+
+    #[test]
+    fn logical_hash() {
+        let attrs = |pairs: &[(&str, &str)]| {
+            let map: BTreeMap<Box<str>, XAttribute> = pairs
+                .iter()
+                .map(|(k, v)| ((*k).into(), XAttribute { namespace: None, value: (*v).into() }))
+                .collect();
+            Some(map)
+        };
+
+        // <root><a x="1" y="2">hi there</a></root>
+        let mut builder = crate::TreeBuilder::new();
+        builder.start_element(None, "root", None);
+        builder.start_element(None, "a", attrs(&[("x", "1"), ("y", "2")]));
+        builder.text("hi there");
+        let compact = builder.finish();
+
+        // The same, indented, with a comment and an unused declaration.
+        let mut builder = crate::TreeBuilder::new();
+        builder.add_namespace("p".into(), "urn:unused".into());
+        builder.start_element(None, "root", attrs(&[("xmlns:p", "urn:unused")]));
+        builder.text("\n  ");
+        builder.comment(" note ");
+        builder.start_element(None, "a", attrs(&[("y", "2"), ("x", "1")]));
+        builder.text("\n    hi\n    there\n  ");
+        builder.end_element();
+        builder.text("\n");
+        let pretty = builder.finish();
+
+        assert_eq!(compact.logical_hash(), pretty.logical_hash());
+
+        let mut changed = compact;
+        let a = changed.node(1).unwrap();
+        changed.set_attribute(&a, "x", "3");
+        assert_ne!(changed.logical_hash(), pretty.logical_hash());
+    }
+
+    #[test]
+    fn split_qualified_names() {
+        assert_eq!(split_qname("a:b"), (Some("a"), "b"));
+        assert_eq!(split_qname("b"), (None, "b"));
+        assert_eq!(split_qname(":x"), (Some(""), "x"));
+        assert_eq!(split_qname("a:b:c"), (Some("a"), "b:c"));
+    }
+
+    #[test]
+    fn names_at_depth() {
+        let mut tree = sample_tree();
+        assert_eq!(tree.names_at_depth(1), ["root"]);
+        assert_eq!(tree.names_at_depth(2), ["child"]);
+        assert!(tree.names_at_depth(3).is_empty());
+
+        let root = tree.node(0).unwrap();
+        for name in ["other", "child"] {
+            root.push(&mut tree, XNode::Tag { namespace: None, name: name.into(), attributes: None, self_closing: true });
+        }
+        assert_eq!(tree.names_at_depth(2), ["child", "other"]);
+    }
+
+    #[test]
+    fn attribute_index() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        tree.set_attribute(&root, "xmlns", "http://example.com");
+        let second = root.push(
+            &mut tree,
+            XNode::Tag {
+                namespace: None,
+                name: "child".into(),
+                attributes: None,
+                self_closing: true,
+            },
+        );
+        tree.set_attribute(&second, "id", "2");
+
+        let index = tree.attribute_index();
+        let names = |element: &str| -> Vec<&str> {
+            index[element].iter().map(|a| a.as_ref()).collect()
+        };
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(names("child"), ["attr", "id"]);
+        assert!(names("root").is_empty());
+    }
+
     #[test]
     fn parent_navigation() {
         let tree = sample_tree();
@@ -495,6 +2037,54 @@ mod tests {
         assert_eq!(grandchildren, vec![2]); // "text"
     }
 
+    #[test]
+    fn descendants_at_depth_navigation() {
+        let tree = sample_tree();
+        let root = tree.node(0).unwrap();
+
+        let grandchildren: Vec<usize> = root
+            .descendants_at_depth(&tree, 2)
+            .iter()
+            .map(|n| n.index())
+            .collect();
+        assert_eq!(grandchildren, vec![2]); // "text"
+
+        let children: Vec<usize> = root
+            .iter_descendants_at_depth(&tree, 1)
+            .map(|n| n.index())
+            .collect();
+        assert_eq!(children, vec![1, 3]);
+
+        assert!(root.descendants_at_depth(&tree, 0).is_empty());
+        assert!(root.descendants_at_depth(&tree, 3).is_empty());
+    }
+
+    #[test]
+    fn leaf_and_children_predicates() {
+        let tree = sample_tree();
+        assert!(tree.node(0).unwrap().has_children(&tree));
+        assert!(tree.node(1).unwrap().has_children(&tree));
+        assert!(tree.node(2).unwrap().is_leaf(&tree));
+        assert!(tree.node(3).unwrap().is_leaf(&tree));
+
+        let empty = empty_tree();
+        assert!(empty.node(0).unwrap().is_leaf(&empty));
+        assert!(!Node { index: usize::MAX }.has_children(&empty));
+    }
+
+    #[test]
+    fn postorder_iteration() {
+        let tree = sample_tree();
+        let order: Vec<usize> = tree.iter_postorder().map(|n| n.index()).collect();
+        assert_eq!(order, vec![2, 1, 3, 0]);
+
+        let forest = empty_tree();
+        let order: Vec<usize> = forest.iter_postorder().map(|n| n.index()).collect();
+        assert_eq!(order, vec![0, 1]);
+
+        assert_eq!(FlatTree::new().iter_postorder().count(), 0);
+    }
+
     #[test]
     fn sibling_navigation() {
         let tree = sample_tree();
@@ -514,6 +2104,23 @@ mod tests {
         assert!(tree.node(1).unwrap().prev_sibling(&tree).is_none());
     }
 
+    #[test]
+    fn sibling_iterators() {
+        let mut tree = sample_tree();
+        let index = |nodes: &mut dyn Iterator<Item = Node>| nodes.map(|n| n.index()).collect::<Vec<_>>();
+
+        let child = tree.node(1).unwrap();
+        assert_eq!(index(&mut child.following_siblings(&tree)), [3]);
+        assert!(index(&mut child.preceding_siblings(&tree)).is_empty());
+
+        let root = tree.node(0).unwrap();
+        root.push(&mut tree, XNode::Text("tail".into()));
+        let tail = tree.node(4).unwrap();
+        assert_eq!(index(&mut tail.preceding_siblings(&tree)), [3, 1]);
+        assert_eq!(index(&mut child.following_siblings(&tree)), [3, 4]);
+        assert!(index(&mut tree.document().following_siblings(&tree)).is_empty());
+    }
+
     #[test]
     fn ancestors_and_descendants() {
         let tree = sample_tree();
@@ -528,6 +2135,76 @@ mod tests {
         assert_eq!(desc, vec![1, 2, 3]);
     }
 
+    #[test]
+    fn text_len() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        assert_eq!(root.text_len(&tree), root.text_content(&tree).len());
+        assert_eq!(root.text_len(&tree), 4);
+
+        root.push(&mut tree, XNode::Text("café".into()));
+        assert_eq!(root.text_len(&tree), 9);
+        assert_eq!(tree.document().text_len(&tree), tree.document().text_content(&tree).len());
+        assert_eq!(tree.node(3).unwrap().text_len(&tree), 0);
+        assert_eq!(Node { index: 10 }.text_len(&tree), 0);
+    }
+
+    #[test]
+    fn node_write_canonical() {
+        let tree = sample_tree();
+        for node in [tree.document(), tree.node(0).unwrap(), tree.node(1).unwrap(), tree.node(2).unwrap()] {
+            let mut out = Vec::new();
+            node.write_canonical(&tree, &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), node.canonicalize(&tree));
+        }
+        assert_eq!(tree.node(1).unwrap().canonicalize(&tree), r#"<child attr="val">text</child>"#);
+    }
+
+    #[test]
+    fn or_self_axes() {
+        let tree = sample_tree();
+        let indices = |nodes: &mut dyn Iterator<Item = Node>| nodes.map(|node| node.index()).collect::<Vec<_>>();
+        let [root, child, text] = [0, 1, 2].map(|i| tree.node(i).unwrap());
+
+        assert_eq!(indices(&mut text.ancestor_or_self(&tree)), [2, 1, 0]);
+        assert_eq!(indices(&mut root.ancestor_or_self(&tree)), [0]);
+
+        assert_eq!(indices(&mut root.descendant_or_self(&tree)), [0, 1, 2, 3]);
+        assert_eq!(indices(&mut child.descendant_or_self(&tree)), [1, 2]);
+        assert_eq!(indices(&mut text.descendant_or_self(&tree)), [2]);
+
+        let document = tree.document();
+        assert_eq!(document.descendant_or_self(&tree).count(), 5);
+        assert_eq!(document.descendant_or_self(&tree).next(), Some(document));
+        assert_eq!(Node { index: 10 }.descendant_or_self(&tree).count(), 0);
+    }
+
+    #[test]
+    fn ancestor_and_descendant_checks() {
+        let tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        let child = tree.node(1).unwrap();
+        let text = tree.node(2).unwrap();
+        let comment = tree.node(3).unwrap();
+
+        assert!(root.is_ancestor_of(&tree, &text));
+        assert!(!text.is_ancestor_of(&tree, &root));
+        assert!(text.is_descendant_of(&tree, &root));
+        assert!(!child.is_ancestor_of(&tree, &comment));
+        assert!(!child.is_ancestor_of(&tree, &child));
+        assert!(tree.document().is_ancestor_of(&tree, &comment));
+        assert!(!root.is_ancestor_of(&tree, &tree.document()));
+    }
+
+    #[test]
+    fn descendant_count() {
+        let tree = sample_tree();
+        for node in tree.iter().chain([tree.document(), Node { index: 10 }]) {
+            assert_eq!(node.descendant_count(&tree), node.descendants(&tree).len());
+        }
+        assert_eq!(tree.node(0).unwrap().descendant_count(&tree), 3);
+    }
+
     #[test]
     fn subtree_end() {
         let tree = sample_tree();
@@ -541,6 +2218,76 @@ mod tests {
         assert_eq!(tree.node(3).unwrap().subtree_end(&tree), 4);
     }
 
+    #[test]
+    fn push_tag_with_namespace() {
+        let soap = "http://schemas.xmlsoap.org/soap/envelope/";
+        let mut tree = FlatTree::new();
+        let envelope = tree.document().push_tag_ns(&mut tree, "soap", soap, "Envelope");
+        let body = envelope.push_tag_ns(&mut tree, "soap", soap, "Body");
+
+        let Some(XNode::Tag { namespace, name, .. }) = body.value(&tree) else {
+            panic!("expected a tag");
+        };
+        assert_eq!(&**name, "Body");
+        assert_eq!(tree.get_namespace(*namespace), Some(("soap", soap)));
+        assert_eq!(tree.namespace_count(), 1);
+        assert_eq!(tree.find_node("soap:Body"), Some(body));
+        assert_eq!(
+            crate::canonicalize(&tree, &tree.document()),
+            format!(r#"<soap:Envelope xmlns:soap="{soap}"><soap:Body></soap:Body></soap:Envelope>"#)
+        );
+    }
+
+    #[test]
+    fn in_scope_namespaces() {
+        // <root xmlns="urn:d" xmlns:a="urn:a"><child xmlns:b="urn:b">
+        //   <leaf xmlns="" xmlns:a="urn:a2"/>text</child></root>
+        let mut builder = crate::TreeBuilder::new();
+        let root = builder.start_element(None, "root", None);
+        let child = builder.start_element(None, "child", None);
+        let leaf = builder.empty_element(None, "leaf", None);
+        builder.text("text");
+        let mut tree = builder.finish();
+        tree.set_attribute(&root, "xmlns", "urn:d");
+        tree.set_attribute(&root, "xmlns:a", "urn:a");
+        tree.set_attribute(&child, "xmlns:b", "urn:b");
+        tree.set_attribute(&leaf, "xmlns", "");
+        tree.set_attribute(&leaf, "xmlns:a", "urn:a2");
+
+        assert_eq!(leaf.in_scope_namespaces(&tree), [(Some("a"), "urn:a2"), (Some("b"), "urn:b")]);
+        assert_eq!(
+            tree.node(3).unwrap().in_scope_namespaces(&tree),
+            [(None, "urn:d"), (Some("a"), "urn:a"), (Some("b"), "urn:b")]
+        );
+        assert_eq!(root.in_scope_namespaces(&tree), [(None, "urn:d"), (Some("a"), "urn:a")]);
+        assert!(tree.document().in_scope_namespaces(&tree).is_empty());
+    }
+
+    #[test]
+    fn try_parent() {
+        let tree = sample_tree();
+        assert_eq!(tree.node(2).unwrap().try_parent(&tree), Ok(tree.node(1).unwrap()));
+        assert_eq!(tree.node(0).unwrap().try_parent(&tree), Err(NavError::IsRoot));
+        assert_eq!(tree.document().try_parent(&tree), Err(NavError::Sentinel));
+
+        let stale = tree.node(3).unwrap();
+        assert_eq!(stale.try_parent(&FlatTree::new()), Err(NavError::Stale));
+        assert!(stale.parent(&FlatTree::new()).is_none());
+    }
+
+    #[test]
+    fn last_descendant() {
+        let tree = sample_tree();
+        let last = |index: usize| tree.node(index).unwrap().last_descendant(&tree).map(|n| n.index());
+        assert_eq!(last(0), Some(3));
+        assert!(matches!(tree.value(3), Some(XNode::Comment(_))));
+        assert_eq!(last(1), Some(2));
+        assert_eq!(last(2), None);
+        assert_eq!(last(3), None);
+        assert_eq!(tree.document().last_descendant(&tree).map(|n| n.index()), Some(3));
+        assert_eq!(FlatTree::new().document().last_descendant(&FlatTree::new()), None);
+    }
+
     #[test]
     fn sentinel_node_push() {
         // The usize::MAX sentinel from as_node on an empty tree
@@ -555,6 +2302,235 @@ mod tests {
         assert_eq!(root.depth(&tree), 1);
     }
 
+    #[test]
+    fn attribute_mutation() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+
+        let previous = tree.set_attribute(&root, "id", "r1");
+        assert!(previous.is_none());
+        match root.value(&tree) {
+            Some(XNode::Tag { attributes: Some(attributes), .. }) => {
+                assert_eq!(&*attributes.get("id").unwrap().value, "r1");
+            }
+            _ => panic!("expected Tag with attributes"),
+        }
+
+        let removed = tree.remove_attribute(&root, "id");
+        assert_eq!(&*removed.unwrap().value, "r1");
+        assert!(matches!(root.value(&tree), Some(XNode::Tag { attributes: None, .. })));
+
+        // Non-tags and missing attributes are left alone
+        let text = tree.node(2).unwrap();
+        assert!(tree.set_attribute(&text, "id", "t").is_none());
+        assert!(tree.remove_attribute(&root, "missing").is_none());
+    }
+
+    #[test]
+    fn coalesce_adjacent_text() {
+        let mut tree = FlatTree::new();
+        let root = tree.as_node().push(
+            &mut tree,
+            XNode::Tag {
+                namespace: None,
+                name: "root".into(),
+                attributes: None,
+                self_closing: false,
+            },
+        );
+        root.push(&mut tree, XNode::Text("a".into()));
+        root.push(&mut tree, XNode::Text("&".into()));
+        root.push(&mut tree, XNode::Text("b".into()));
+        root.push(&mut tree, XNode::Comment("c".into()));
+        root.push(&mut tree, XNode::Text("d".into()));
+        root.push(&mut tree, XNode::Whitespace(" ".into()));
+        root.push(&mut tree, XNode::Comment("c".into()));
+        root.push(&mut tree, XNode::Whitespace(" ".into()));
+        root.push(&mut tree, XNode::Whitespace("\n".into()));
+
+        tree.coalesce_text();
+
+        assert_eq!(tree.depth_vector(), [1, 2, 2, 2, 2, 2]);
+        assert!(matches!(tree.value(1), Some(XNode::Text(t)) if &**t == "a&b"));
+        // Text with whitespace after it is still text, whitespace alone stays whitespace.
+        assert!(matches!(tree.value(3), Some(XNode::Text(t)) if &**t == "d "));
+        assert!(matches!(tree.value(5), Some(XNode::Whitespace(t)) if &**t == " \n"));
+    }
+
+    #[test]
+    fn shrink_after_with_capacity() {
+        let mut tree = FlatTree::with_capacity(64);
+        tree.push(XNode::Text("a".into()));
+        assert!(tree.nodes.capacity() >= 64);
+
+        tree.shrink_to_fit();
+        assert!(tree.nodes.capacity() < 64);
+        assert!(tree.depth.capacity() < 64);
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn pop_last_node() {
+        let mut tree = sample_tree();
+        assert!(matches!(tree.pop(), Some(XNode::Comment(_))));
+        assert_eq!(tree.depth_vector(), [1, 2, 3]);
+
+        let mut empty = FlatTree::new();
+        assert!(empty.pop().is_none());
+    }
+
+    #[test]
+    fn clone_value() {
+        let mut tree = sample_tree();
+        let child = tree.node(1).unwrap();
+        let Some(XNode::Tag { name, attributes: Some(attributes), .. }) = child.clone_value(&tree) else { panic!() };
+        assert_eq!(&*name, "child");
+        assert_eq!(&*attributes["attr"].value, "val");
+
+        // The copy is independent of the tree.
+        tree.set_attribute(&child, "attr", "changed");
+        assert_eq!(&*attributes["attr"].value, "val");
+        assert!(tree.document().clone_value(&tree).is_none());
+    }
+
+    #[test]
+    fn attributes_as() {
+        let mut tree = sample_tree();
+        let child = tree.node(1).unwrap();
+        assert_eq!(child.attributes_as(&tree, &["attr"]), [Some("val")]);
+
+        tree.set_attribute(&child, "id", "7");
+        let [id, name, attr] = child.attributes_as(&tree, &["id", "name", "attr"]);
+        assert_eq!((id, name, attr), (Some("7"), None, Some("val")));
+        assert_eq!(tree.node(0).unwrap().attributes_as(&tree, &["attr"]), [None]);
+        assert_eq!(tree.node(2).unwrap().attributes_as(&tree, &["attr", "id"]), [None, None]);
+    }
+
+    #[test]
+    fn find_child_by_name() {
+        // <root><wrap><child/></wrap><child/></root>
+        let mut builder = crate::TreeBuilder::new();
+        builder.start_element(None, "root", None);
+        builder.start_element(None, "wrap", None);
+        builder.empty_element(None, "child", None);
+        builder.end_element();
+        builder.empty_element(None, "child", None);
+        builder.end_element();
+        let tree = builder.finish();
+        let root = tree.node(0).unwrap();
+
+        assert_eq!(root.find_child_by_name(&tree, "child"), tree.node(3));
+        assert_eq!(tree.node(1).unwrap().find_child_by_name(&tree, "child"), tree.node(2));
+        assert_eq!(root.find_child_by_name(&tree, "root"), None);
+        assert_eq!(tree.document().find_child_by_name(&tree, "root"), Some(root));
+        assert_eq!(tree.document().find_child_by_name(&tree, "child"), None);
+    }
+
+    #[test]
+    fn normalize_text() {
+        fn tree_with_texts(texts: &[&str]) -> FlatTree {
+            let mut builder = crate::TreeBuilder::new();
+            builder.start_element(None, "root", None);
+            for text in texts {
+                builder.text(text);
+            }
+            builder.empty_element(None, "e", None);
+            builder.end_element();
+            builder.finish()
+        }
+
+        let mut split = tree_with_texts(&["one\r", "\ntwo\rthree", " four"]);
+        let mut whole = tree_with_texts(&["one\r\ntwo\nthree four"]);
+        split.normalize(NormalizeOptions::default());
+        whole.normalize(NormalizeOptions::default());
+
+        assert_eq!(split.depth_vector(), whole.depth_vector());
+        assert_eq!(split.depth_vector(), [1, 2, 2]);
+        assert!(matches!(split.value(1), Some(XNode::Text(text)) if &**text == "one\ntwo\nthree four"));
+        assert_eq!(split.logical_hash(), whole.logical_hash());
+        assert_eq!(crate::canonicalize(&split, &split.document()), crate::canonicalize(&whole, &whole.document()));
+    }
+
+    #[test]
+    fn normalize_whitespace() {
+        let build = || {
+            let mut builder = crate::TreeBuilder::new();
+            builder.start_element(None, "root", None);
+            builder.text("\n  ");
+            let mut attributes = BTreeMap::new();
+            attributes.insert("xml:space".into(), XAttribute { namespace: None, value: "preserve".into() });
+            builder.start_element(None, "pre", Some(attributes));
+            builder.text("  ");
+            builder.end_element();
+            builder.text(" \r\n");
+            builder.end_element();
+            builder.finish()
+        };
+
+        let mut kept = build();
+        kept.normalize(NormalizeOptions::default());
+        assert_eq!(kept.depth_vector(), [1, 2, 2, 3, 2]);
+
+        let mut tree = build();
+        tree.normalize(NormalizeOptions { trim_whitespace: true, ..NormalizeOptions::default() });
+        assert_eq!(tree.depth_vector(), [1, 2, 3]);
+        assert!(matches!(tree.value(2), Some(XNode::Text(text)) if &**text == "  "));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn reserved_xml_namespace() {
+        let mut tree = sample_tree();
+        let child = tree.node(1).unwrap();
+        tree.set_attribute(&child, "xml:lang", "en");
+
+        let Some(XNode::Tag { attributes: Some(attributes), .. }) = child.value(&tree) else { panic!() };
+        let id = attributes["xml:lang"].namespace;
+        assert_eq!(tree.get_namespace(id), Some(("xml", XML_NAMESPACE)));
+        assert_eq!(tree.add_namespace("xml".into(), XML_NAMESPACE.into()), id);
+
+        assert_eq!(tree.add_namespace("xml".into(), "urn:other".into()), None);
+        assert_eq!(tree.add_namespace("x".into(), XML_NAMESPACE.into()), None);
+        assert_eq!(tree.namespace_count(), 1);
+    }
+
+    #[test]
+    fn namespace_registry_boundary() {
+        let mut tree = FlatTree::new();
+        for i in 0..u16::MAX {
+            assert_eq!(tree.add_namespace(format!("p{i}").into(), "urn:p".into()), Some(i));
+        }
+        assert_eq!(tree.namespace_count(), 65535);
+
+        assert_eq!(tree.add_namespace("last".into(), "urn:last".into()), Some(u16::MAX));
+        assert_eq!(tree.namespace_count(), 65536);
+        assert_eq!(tree.get_namespace(Some(u16::MAX)), Some(("last", "urn:last")));
+
+        assert_eq!(tree.add_namespace("over".into(), "urn:over".into()), None);
+        assert_eq!(tree.namespace_count(), 65536);
+        assert_eq!(tree.find_namespace(Some("over")), None);
+        // Registered bindings are still found when full.
+        assert_eq!(tree.add_namespace("last".into(), "urn:last".into()), Some(u16::MAX));
+        assert_eq!(tree.add_namespace("p7".into(), "urn:p".into()), Some(7));
+    }
+
+    #[test]
+    fn namespace_uris_are_shared() {
+        let uri = "http://schemas.example.com/some/long/namespace/uri/2024";
+        let mut tree = FlatTree::new();
+        let a = tree.add_namespace("a".into(), uri.into());
+        let b = tree.add_namespace("b".into(), uri.into());
+        let other = tree.add_namespace("c".into(), "urn:other".into());
+        assert_ne!(a, b);
+
+        let (_, a_uri) = tree.get_namespace(a).unwrap();
+        let (_, b_uri) = tree.get_namespace(b).unwrap();
+        assert_eq!(a_uri, uri);
+        assert_eq!(a_uri.as_ptr(), b_uri.as_ptr());
+        assert_ne!(tree.namespace_uri(other).unwrap().as_ptr(), a_uri.as_ptr());
+        assert_eq!(Arc::strong_count(&tree.namespaces[0].1), 2);
+    }
+
     #[test]
     fn namespace_registry() {
         let mut tree = FlatTree::new();
@@ -568,6 +2544,138 @@ mod tests {
         assert_eq!(tree.find_namespace(Some("missing")), None);
     }
 
+    #[test]
+    fn remap_namespace_prefix() {
+        let mut tree = FlatTree::new();
+        let soap = tree.add_namespace("soap".into(), "http://soap".into());
+        tree.add_namespace("other".into(), "http://other".into());
+
+        let envelope = tree.push(XNode::Tag {
+            namespace: soap,
+            name: "Envelope".into(),
+            attributes: None,
+            self_closing: false,
+        });
+        tree.set_attribute(&envelope, "xmlns:soap", "http://soap");
+        tree.set_attribute(&envelope, "soap:mustUnderstand", "1");
+        envelope.push(
+            &mut tree,
+            XNode::Tag {
+                namespace: soap,
+                name: "Body".into(),
+                attributes: None,
+                self_closing: true,
+            },
+        );
+
+        // `other` is bound to a different uri
+        assert!(!tree.remap_prefix("soap", "other"));
+        assert!(!tree.remap_prefix("missing", "s"));
+
+        assert!(tree.remap_prefix("soap", "s"));
+        assert_eq!(tree.find_namespace(Some("s")), soap);
+        assert_eq!(tree.find_namespace(Some("soap")), None);
+        assert_eq!(tree.get_namespace(soap), Some(("s", "http://soap")));
+        assert_eq!(tree.find_node("s:Body").unwrap().index(), 1);
+
+        let Some(XNode::Tag { attributes: Some(attributes), .. }) = tree.value(0) else {
+            panic!("expected Tag with attributes");
+        };
+        let keys: Vec<&str> = attributes.keys().map(|k| k.as_ref()).collect();
+        assert_eq!(keys, ["s:mustUnderstand", "xmlns:s"]);
+    }
+
+    #[test]
+    fn remap_reserved_prefixes() {
+        let mut tree = FlatTree::new();
+        let s = tree.add_namespace("s".into(), "http://soap".into());
+        let default = tree.add_namespace("".into(), "urn:d".into());
+        let envelope = tree.push(XNode::Tag {
+            namespace: s,
+            name: "Envelope".into(),
+            attributes: None,
+            self_closing: true,
+        });
+        // Not taken yet, but still reserved.
+        assert!(!tree.remap_prefix("s", "xml"));
+
+        tree.set_attribute(&envelope, "xml:lang", "en");
+        let xml = tree.find_namespace(Some("xml"));
+        assert!(xml.is_some());
+
+        // Into or out of the default namespace.
+        assert!(!tree.remap_prefix("s", ""));
+        assert!(!tree.remap_prefix("", "d"));
+        assert_eq!(tree.get_namespace(s), Some(("s", "http://soap")));
+        assert_eq!(tree.get_namespace(default), Some(("", "urn:d")));
+
+        // Out of `xml`.
+        assert!(!tree.remap_prefix("xml", "x"));
+        assert_eq!(tree.get_namespace(xml), Some(("xml", XML_NAMESPACE)));
+        assert_eq!(tree.get_namespace(s), Some(("s", "http://soap")));
+        assert!(tree.find_node("s:Envelope").is_some());
+    }
+
+    #[test]
+    fn compact_namespaces() {
+        let mut tree = sample_tree();
+        let a = tree.add_namespace("a".into(), "urn:a".into()).unwrap();
+        let b = tree.add_namespace("b".into(), "urn:b".into()).unwrap();
+        let root = tree.node(0).unwrap();
+        let child = tree.node(1).unwrap();
+        if let Some(XNode::Tag { namespace, .. }) = tree.value_mut(1) {
+            *namespace = Some(a);
+        }
+        tree.set_attribute(&root, "b:attr", "1");
+
+        // The only user of `a` goes away.
+        tree.remove_subtree(&child);
+        tree.compact_namespaces();
+
+        assert_eq!(tree.namespaces().collect::<Vec<_>>(), [(0, "b", "urn:b")]);
+        assert_eq!(tree.find_namespace(Some("b")), Some(0));
+        assert_eq!(tree.find_namespace(Some("a")), None);
+        let Some(XNode::Tag { attributes: Some(attributes), .. }) = tree.value(0) else { panic!() };
+        assert_eq!(attributes["b:attr"].namespace, Some(0));
+        assert_eq!(b, 1);
+
+        tree.compact_namespaces();
+        assert_eq!(tree.namespace_count(), 1);
+    }
+
+    #[test]
+    fn used_namespaces() {
+        let mut tree = sample_tree();
+        let a = tree.add_namespace("a".into(), "urn:a".into()).unwrap();
+        let unused = tree.add_namespace("unused".into(), "urn:unused".into()).unwrap();
+        let b = tree.add_namespace("b".into(), "urn:b".into()).unwrap();
+
+        let root = tree.node(0).unwrap();
+        tree.set_attribute(&root, "xmlns:unused", "urn:unused");
+        if let Some(XNode::Tag { namespace, .. }) = tree.value_mut(1) {
+            *namespace = Some(a);
+        }
+        tree.set_attribute(&root, "b:attr", "1");
+
+        let used = tree.used_namespaces();
+        assert_eq!(used, BTreeSet::from([a, b]));
+        assert!(!used.contains(&unused));
+        assert!(FlatTree::new().used_namespaces().is_empty());
+    }
+
+    #[test]
+    fn namespace_iteration() {
+        let mut tree = FlatTree::new();
+        tree.add_namespace("".into(), "http://example.com".into());
+        tree.add_namespace("ns".into(), "http://ns.example.com".into());
+
+        let namespaces: Vec<(u16, &str, &str)> = tree.namespaces().collect();
+        assert_eq!(
+            namespaces,
+            vec![(0, "", "http://example.com"), (1, "ns", "http://ns.example.com")]
+        );
+    }
+
     #[test]
     fn sentinel_navigation_does_not_panic() {
         let tree = sample_tree();
@@ -586,6 +2694,399 @@ mod tests {
         assert_eq!(sentinel.subtree_end(&tree), 0);
     }
 
+    #[test]
+    fn extract_subtree_prunes_namespaces() {
+        // <a:root xmlns:a="urn:a" xmlns:b="urn:b" xmlns:c="urn:c"><b:item c="1"><x/></b:item></a:root>
+        let mut tree = FlatTree::new();
+        let a = tree.add_namespace("a".into(), "urn:a".into());
+        let b = tree.add_namespace("b".into(), "urn:b".into());
+        tree.add_namespace("c".into(), "urn:c".into());
+
+        let mut declarations = BTreeMap::new();
+        for (prefix, uri) in [("a", "urn:a"), ("b", "urn:b"), ("c", "urn:c")] {
+            declarations.insert(
+                format!("xmlns:{prefix}").into(),
+                XAttribute { namespace: None, value: uri.into() },
+            );
+        }
+        let root = tree.as_node().push(
+            &mut tree,
+            XNode::Tag { namespace: a, name: "root".into(), attributes: Some(declarations), self_closing: false },
+        );
+        let item = root.push(
+            &mut tree,
+            XNode::Tag { namespace: b, name: "item".into(), attributes: None, self_closing: false },
+        );
+        tree.set_attribute(&item, "c", "1");
+        item.push(
+            &mut tree,
+            XNode::Tag { namespace: None, name: "x".into(), attributes: None, self_closing: true },
+        );
+
+        let extracted = item.extract_subtree(&tree);
+        assert_eq!(extracted.depth_vector(), [1, 2]);
+        assert_eq!(extracted.namespaces().collect::<Vec<_>>(), [(0, "b", "urn:b")]);
+
+        let Some(XNode::Tag { namespace, attributes: Some(attributes), .. }) = extracted.value(0) else {
+            panic!("expected the item element");
+        };
+        assert_eq!(*namespace, Some(0));
+        let keys: Vec<&str> = attributes.keys().map(|k| &**k).collect();
+        assert_eq!(keys, ["c", "xmlns:b"]);
+
+        // The unprefixed `c` attribute does not pull in the `c` namespace.
+        assert!(extracted.find_namespace(Some("c")).is_none());
+    }
+
+    #[test]
+    fn name_index_matches_scan() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        for name in ["child", "other", "child"] {
+            root.push(&mut tree, XNode::Tag { namespace: None, name: name.into(), attributes: None, self_closing: true });
+        }
+
+        let index = tree.name_index();
+        for name in ["root", "child", "other", "missing"] {
+            assert_eq!(index.find_all(name).collect::<Vec<_>>(), tree.find_all_by_name(name));
+            assert_eq!(index.find_first(name), tree.find_node_by_name(name));
+        }
+        let children: Vec<usize> = index.find_all("child").map(|n| n.index()).collect();
+        assert_eq!(children, [1, 4, 6]);
+    }
+
+    #[test]
+    fn document_element_skips_prolog() {
+        let mut tree = FlatTree::new();
+        let document = tree.document();
+        document.push(&mut tree, XNode::ProcessingInstruction { target: "pi".into(), data: None });
+        document.push(&mut tree, XNode::Comment("c".into()));
+        let root = document.push(&mut tree, XNode::Tag { namespace: None, name: "root".into(), attributes: None, self_closing: true });
+
+        assert_eq!(tree.document_element(), Some(root));
+        assert_eq!(sample_tree().document_element().map(|n| n.index()), Some(0));
+        assert_eq!(FlatTree::new().document_element(), None);
+    }
+
+    #[test]
+    fn iterate_by_kind() {
+        let tree = sample_tree();
+        let indices = |nodes: &mut dyn Iterator<Item = Node>| nodes.map(|n| n.index()).collect::<Vec<_>>();
+
+        assert_eq!(indices(&mut tree.iter()), [0, 1, 2, 3]);
+        assert_eq!(indices(&mut tree.tags()), [0, 1]);
+        assert_eq!(indices(&mut tree.texts()), [2]);
+        assert_eq!(indices(&mut tree.comments()), [3]);
+        assert_eq!(tree.pis().count(), 0);
+    }
+
+    #[test]
+    fn validate_depths() {
+        let mut tree = sample_tree();
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(FlatTree::new().validate(), Ok(()));
+
+        tree.depth[2] = 4;
+        assert_eq!(tree.validate(), Err(TreeError::DepthJump { index: 2, depth: 4, previous: 2 }));
+
+        tree.depth[2] = 3;
+        tree.depth[0] = 2;
+        assert_eq!(tree.validate(), Err(TreeError::DepthJump { index: 0, depth: 2, previous: 0 }));
+
+        tree.depth[0] = 1;
+        tree.depth.push(1);
+        assert_eq!(tree.validate(), Err(TreeError::LengthMismatch { nodes: 4, depth: 5 }));
+    }
+
+    #[test]
+    fn map_nodes() {
+        let mut tree = sample_tree();
+        let a = tree.add_namespace("a".into(), "urn:a".into()).unwrap();
+
+        let mapped = tree.map_nodes(|xnode| match xnode {
+            XNode::Tag { namespace, name, attributes, self_closing } => XNode::Tag {
+                namespace: if &**name == "root" { Some(a) } else { *namespace },
+                name: name.to_uppercase().into(),
+                attributes: attributes.clone(),
+                self_closing: *self_closing,
+            },
+            other => other.clone(),
+        });
+
+        assert_eq!(mapped.depth_vector(), tree.depth_vector());
+        assert_eq!(mapped.validate(), Ok(()));
+        assert!(matches!(mapped.value(0), Some(XNode::Tag { namespace: Some(id), name, .. }) if *id == a && &**name == "ROOT"));
+        assert!(matches!(mapped.value(1), Some(XNode::Tag { name, attributes: Some(attributes), .. })
+            if &**name == "CHILD" && &*attributes["attr"].value == "val"));
+        assert!(matches!(mapped.value(2), Some(XNode::Text(text)) if &**text == "text"));
+        assert_eq!(mapped.namespace_uri(Some(a)), Some("urn:a"));
+
+        // An id from nowhere is dropped rather than left dangling.
+        let mapped = tree.map_nodes(|xnode| match xnode {
+            XNode::Tag { name, attributes, self_closing, .. } => XNode::Tag {
+                namespace: Some(99),
+                name: name.clone(),
+                attributes: attributes.clone(),
+                self_closing: *self_closing,
+            },
+            other => other.clone(),
+        });
+        assert!(matches!(mapped.value(0), Some(XNode::Tag { namespace: None, .. })));
+    }
+
+    #[test]
+    fn replace_subtree() {
+        let mut tree = sample_tree();
+        tree.add_namespace("a".into(), "urn:a".into());
+
+        // <b:new>x</b:new>
+        let mut fragment = FlatTree::new();
+        let b = fragment.add_namespace("b".into(), "urn:b".into());
+        let new = fragment.as_node().push(
+            &mut fragment,
+            XNode::Tag { namespace: b, name: "new".into(), attributes: None, self_closing: false },
+        );
+        new.push(&mut fragment, XNode::Text("x".into()));
+
+        let child = tree.node(1).unwrap();
+        assert!(tree.replace_subtree(&child, &fragment));
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+
+        let Some(XNode::Tag { namespace, name, .. }) = tree.value(1) else {
+            panic!("expected the new element");
+        };
+        assert_eq!(&**name, "new");
+        assert_eq!(tree.get_namespace(*namespace), Some(("b", "urn:b")));
+        assert!(matches!(tree.value(2), Some(XNode::Text(text)) if &**text == "x"));
+        assert!(matches!(tree.value(3), Some(XNode::Comment(_))));
+
+        assert!(!tree.replace_subtree(&tree.document(), &fragment));
+    }
+
+    #[test]
+    fn replace_subtree_failures() {
+        // 255 levels deep, one too many under the root.
+        let mut deep = FlatTree::new();
+        let mut node = deep.as_node();
+        for _ in 0..255 {
+            node = node.push(&mut deep, XNode::Tag { namespace: None, name: "d".into(), attributes: None, self_closing: false });
+        }
+        let mut tree = sample_tree();
+        let child = tree.node(1).unwrap();
+        assert!(!tree.replace_subtree(&child, &deep));
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+        let root = tree.node(0).unwrap();
+        assert!(tree.replace_subtree(&root, &deep));
+        assert_eq!(tree.len(), 255);
+
+        // Room for one more namespace but the replacement brings two.
+        let mut tree = sample_tree();
+        tree.add_namespace("known".into(), "urn:known".into());
+        for i in 1..u16::MAX {
+            tree.add_namespace(format!("p{i}").into(), "urn:p".into());
+        }
+        let mut fragment = FlatTree::new();
+        fragment.add_namespace("known".into(), "urn:known".into());
+        let first = fragment.add_namespace("first".into(), "urn:first".into());
+        fragment.add_namespace("second".into(), "urn:second".into());
+        fragment.push(XNode::Tag { namespace: first, name: "new".into(), attributes: None, self_closing: true });
+
+        let child = tree.node(1).unwrap();
+        assert!(!tree.replace_subtree(&child, &fragment));
+        assert_eq!(tree.namespace_count(), 65535);
+        assert_eq!(tree.find_namespace(Some("first")), None);
+        assert_eq!(tree.find_namespace(Some("known")), Some(0));
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+        // The entry that did not get used is still free.
+        assert_eq!(tree.add_namespace("first".into(), "urn:first".into()), Some(u16::MAX));
+    }
+
+    #[test]
+    fn duplicate_subtree() {
+        let mut tree = sample_tree();
+        let root = tree.node(0).unwrap();
+        let child = tree.node(1).unwrap();
+
+        let copy = tree.duplicate_subtree(&child, &root).unwrap();
+        assert_eq!(copy.index(), 4);
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2, 2, 3]);
+        assert!(copy.compare_name(&tree, None, "child"));
+        assert_eq!(copy.text_content(&tree), "text");
+
+        // Into itself: the original subtree is what gets copied.
+        let copy = tree.duplicate_subtree(&child, &child).unwrap();
+        assert_eq!(copy.index(), 3);
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 3, 4, 2, 2, 3]);
+
+        let top = tree.duplicate_subtree(&child, &tree.document()).unwrap();
+        assert_eq!(tree.depth_vector()[top.index()..], [1, 2, 2, 3]);
+        assert!(tree.validate().is_ok());
+
+        assert!(tree.duplicate_subtree(&tree.document(), &root).is_none());
+    }
+
+    #[test]
+    fn detach_subtree() {
+        let mut tree = sample_tree();
+        let child = tree.node(1).unwrap();
+        let detached = tree.detach(&child);
+
+        assert_eq!(tree.depth_vector(), [1, 2]);
+        assert!(matches!(tree.value(1), Some(XNode::Comment(_))));
+        assert_eq!(detached.depth_vector(), [1, 2]);
+        assert!(matches!(detached.value(0), Some(XNode::Tag { name, .. }) if &**name == "child"));
+        assert!(matches!(detached.value(1), Some(XNode::Text(text)) if &**text == "text"));
+
+        assert!(!tree.remove_subtree(&tree.document()));
+        assert!(tree.remove_subtree(&tree.node(0).unwrap()));
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn source_spans_follow_edits() {
+        let mut tree = sample_tree();
+        assert!(!tree.set_source_spans(vec![(0, 1); 5]));
+        assert!(tree.set_source_spans(vec![(0, 40), (6, 31), (24, 28), (31, 47)]));
+        assert_eq!(tree.node(2).unwrap().source_span(&tree), Some((24, 28)));
+        assert_eq!(tree.document().source_span(&tree), None);
+
+        tree.pop();
+        let comment = tree.node(0).unwrap().push(&mut tree, XNode::Comment("new".into()));
+        assert_eq!(comment.source_span(&tree), None);
+        assert_eq!(tree.node(1).unwrap().source_span(&tree), Some((6, 31)));
+
+        tree.remove_subtree(&tree.node(1).unwrap());
+        assert_eq!(tree.node(0).unwrap().source_span(&tree), Some((0, 40)));
+
+        tree.strip_non_element_noise();
+        assert_eq!(tree.node(0).unwrap().source_span(&tree), None);
+    }
+
+    #[test]
+    fn subtree_slice() {
+        let tree = sample_tree();
+        for node in [tree.node(0).unwrap(), tree.node(1).unwrap(), tree.node(3).unwrap()] {
+            let (nodes, depth) = node.subtree_slice(&tree);
+            assert_eq!(nodes.len(), node.descendants(&tree).len() + 1);
+            assert_eq!(depth.len(), nodes.len());
+        }
+
+        let (nodes, depth) = tree.node(1).unwrap().subtree_slice(&tree);
+        assert_eq!(depth, [2, 3]);
+        assert!(matches!(&nodes[1], XNode::Text(text) if &**text == "text"));
+        assert_eq!(tree.document().subtree_slice(&tree).0.len(), 0);
+    }
+
+    #[test]
+    fn as_text_value() {
+        let tree = sample_tree();
+        assert_eq!(tree.node(1).unwrap().as_text_value(&tree), Some("text"));
+        assert_eq!(tree.node(0).unwrap().as_text_value(&tree), None);
+        assert_eq!(tree.node(2).unwrap().as_text_value(&tree), None);
+        assert_eq!(tree.document().as_text_value(&tree), None);
+    }
+
+    #[test]
+    fn text_content() {
+        // <p>Hello <b>big</b> world<!--x--></p>
+        let mut tree = FlatTree::new();
+        let tag = |name: &str| XNode::Tag { namespace: None, name: name.into(), attributes: None, self_closing: false };
+        let p = tree.as_node().push(&mut tree, tag("p"));
+        p.push(&mut tree, XNode::Text("Hello".into()));
+        let b = p.push(&mut tree, tag("b"));
+        b.push(&mut tree, XNode::Text("big & bold".into()));
+        p.push(&mut tree, XNode::Text("world".into()));
+        p.push(&mut tree, XNode::Comment("x".into()));
+
+        assert_eq!(p.text_content(&tree), "Hellobig & boldworld");
+        assert_eq!(p.text_content_joined(&tree, " "), "Hello big & bold world");
+        assert_eq!(b.text_content_joined(&tree, " "), "big & bold");
+        assert_eq!(tree.node(1).unwrap().text_content(&tree), "Hello");
+        assert_eq!(tree.document().text_content_joined(&tree, "|"), "Hello|big & bold|world");
+    }
+
+    #[test]
+    fn strip_non_element_noise() {
+        let mut tree = sample_tree();
+        tree.strip_non_element_noise();
+        assert_eq!(tree.depth_vector(), [1, 2, 3]);
+        assert_eq!(tree.node_counts().comments, 0);
+
+        let root = tree.node(0).unwrap();
+        root.push(&mut tree, XNode::ProcessingInstruction { target: "pi".into(), data: None });
+        root.push(&mut tree, XNode::Text("tail".into()));
+        tree.strip_non_element_noise();
+        assert_eq!(tree.depth_vector(), [1, 2, 3, 2]);
+        assert!(matches!(tree.value(3), Some(XNode::Text(text)) if &**text == "tail"));
+    }
+
+    #[test]
+    fn namespace_bindings() {
+        let mut tree = FlatTree::new();
+        let w3 = tree.add_namespace("a".into(), "http://www.w3.org".into());
+        let ietf = tree.add_namespace("a".into(), "http://www.ietf.org".into());
+
+        assert_ne!(w3, ietf);
+        assert_eq!(tree.add_namespace("a".into(), "http://www.w3.org".into()), w3);
+        assert_eq!(tree.find_namespace_binding("a", "http://www.ietf.org"), ietf);
+        assert_eq!(tree.find_namespace(Some("a")), w3);
+        assert_eq!(tree.namespace_count(), 2);
+
+        // Undeclarations bind nothing and do not collide with the default.
+        let default = tree.add_namespace("".into(), "http://default".into());
+        assert_eq!(tree.add_namespace("".into(), "".into()), None);
+        assert_eq!(tree.find_namespace(Some("")), default);
+        assert_eq!(tree.namespace_count(), 3);
+    }
+
+    #[test]
+    fn namespace_uri() {
+        let mut tree = FlatTree::new();
+        tree.add_namespace("".into(), "http://default".into());
+        let soap = tree.add_namespace("soap".into(), "http://schemas.xmlsoap.org/soap/envelope/".into());
+
+        assert_eq!(tree.namespace_uri(soap), Some("http://schemas.xmlsoap.org/soap/envelope/"));
+        assert_eq!(tree.namespace_uri(Some(0)), Some("http://default"));
+        assert_eq!(tree.namespace_uri(None), None);
+        assert_eq!(tree.namespace_uri(Some(7)), None);
+    }
+
+    #[test]
+    fn xpath() {
+        let mut tree = sample_tree();
+        let text = tree.node(2).unwrap();
+        assert_eq!(text.parent(&tree).unwrap().xpath(&tree), "/root/child[1]");
+        assert_eq!(text.xpath(&tree), "/root/child[1]/text()[1]");
+        assert_eq!(tree.node(3).unwrap().xpath(&tree), "/root/comment()[1]");
+        assert_eq!(tree.document().xpath(&tree), "/");
+
+        let ns = tree.add_namespace("p".into(), "urn:p".into());
+        let root = tree.node(0).unwrap();
+        root.push(&mut tree, XNode::Tag { namespace: None, name: "child".into(), attributes: None, self_closing: true });
+        let prefixed = root.push(&mut tree, XNode::Tag { namespace: ns, name: "child".into(), attributes: None, self_closing: true });
+        assert_eq!(tree.node(4).unwrap().xpath(&tree), "/root/child[2]");
+        assert_eq!(prefixed.xpath(&tree), "/root/p:child[1]");
+
+        let tree = empty_tree();
+        assert_eq!(tree.node(1).unwrap().xpath(&tree), "/root2");
+    }
+
+    #[test]
+    fn node_equality() {
+        let tree = sample_tree();
+        assert_eq!(tree.node(1), tree.node(1));
+        assert_ne!(tree.node(1), tree.node(2));
+        assert!(tree.node(0).unwrap().same_as(&tree.node(1).unwrap().parent(&tree).unwrap()));
+        assert!(!tree.node(0).unwrap().same_as(&tree.document()));
+        assert!(FlatTree::new().as_node().same_as(&tree.document()));
+
+        let mut nodes = tree.node(1).unwrap().parent(&tree).unwrap().children(&tree);
+        nodes.insert(0, tree.node(1).unwrap());
+        nodes.dedup();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.contains(&tree.node(3).unwrap()));
+    }
+
     #[test]
     fn stale_node_does_not_panic() {
         let mut tree = FlatTree::new();